@@ -270,15 +270,15 @@ class MempoolBlocks {
     // run the block construction algorithm in a separate thread, and wait for a result
     const rustGbt = saveResults ? this.rustGbtGenerator : new GbtGenerator(config.MEMPOOL.BLOCK_WEIGHT_UNITS, config.MEMPOOL.MEMPOOL_BLOCKS_AMOUNT);
     try {
-      const { blocks, blockWeights, rates, clusters, overflow } = this.convertNapiResultTxids(
+      const { blocks, blockWeights, rates, clusters, overflow, belowMinFee } = this.convertNapiResultTxids(
         await rustGbt.make(transactions as RustThreadTransaction[], convertedAccelerations as RustThreadAcceleration[], this.nextUid),
       );
       if (saveResults) {
         this.rustInitialized = true;
       }
       const expectedSize = transactions.length;
-      const resultMempoolSize = blocks.reduce((total, block) => total + block.length, 0) + overflow.length;
-      logger.debug(`RUST updateBlockTemplates returned ${resultMempoolSize} txs out of ${expectedSize} in the mempool, ${overflow.length} were unmineable`);
+      const resultMempoolSize = blocks.reduce((total, block) => total + block.length, 0) + overflow.length + belowMinFee.length;
+      logger.debug(`RUST updateBlockTemplates returned ${resultMempoolSize} txs out of ${expectedSize} in the mempool, ${overflow.length} were unmineable, ${belowMinFee.length} were below the minimum feerate`);
       const processed = this.processBlockTemplates(newMempool, blocks, blockWeights, rates, clusters, candidates, accelerations, accelerationPool, saveResults, dryRun);
       logger.debug(`RUST makeBlockTemplates completed in ${(Date.now() - start)/1000} seconds`);
       return processed;
@@ -331,7 +331,7 @@ class MempoolBlocks {
 
     // run the block construction algorithm in a separate thread, and wait for a result
     try {
-      const { blocks, blockWeights, rates, clusters, overflow } = this.convertNapiResultTxids(
+      const { blocks, blockWeights, rates, clusters, overflow, belowMinFee } = this.convertNapiResultTxids(
         await this.rustGbtGenerator.update(
           added as RustThreadTransaction[],
           removedTxs.map(tx => tx.uid) as number[],
@@ -339,8 +339,8 @@ class MempoolBlocks {
           this.nextUid,
         ),
       );
-      const resultMempoolSize = blocks.reduce((total, block) => total + block.length, 0) + overflow.length;
-      logger.debug(`RUST updateBlockTemplates returned ${resultMempoolSize} txs out of ${transactions.length} candidates, ${overflow.length} were unmineable`);
+      const resultMempoolSize = blocks.reduce((total, block) => total + block.length, 0) + overflow.length + belowMinFee.length;
+      logger.debug(`RUST updateBlockTemplates returned ${resultMempoolSize} txs out of ${transactions.length} candidates, ${overflow.length} were unmineable, ${belowMinFee.length} were below the minimum feerate`);
       if (transactions.length !== resultMempoolSize) {
         throw new Error(`GBT returned wrong number of transactions ${transactions.length} vs ${resultMempoolSize}, cache is probably out of sync`);
       } else {
@@ -742,8 +742,8 @@ class MempoolBlocks {
     return { blocks: convertedBlocks, rates: convertedRates, clusters: convertedClusters } as { blocks: string[][], rates: { [root: string]: number }, clusters: { [root: string]: string[] }};
   }
 
-  private convertNapiResultTxids({ blocks, blockWeights, rates, clusters, overflow }: GbtResult)
-    : { blocks: string[][], blockWeights: number[], rates: [string, number][], clusters: string[][], overflow: string[] } {
+  private convertNapiResultTxids({ blocks, blockWeights, rates, clusters, overflow, belowMinFee }: GbtResult)
+    : { blocks: string[][], blockWeights: number[], rates: [string, number][], clusters: string[][], overflow: string[], belowMinFee: string[] } {
     const convertedBlocks: string[][] = blocks.map(block => block.map(uid => {
       const txid = this.uidMap.get(uid);
       if (txid !== undefined) {
@@ -769,7 +769,16 @@ class MempoolBlocks {
         throw new Error('GBT returned an unmineable transaction with unknown uid');
       }
     });
-    return { blocks: convertedBlocks, blockWeights, rates: convertedRates, clusters: convertedClusters, overflow: convertedOverflow };
+    // packages below the minimum feerate are in neither the blocks nor the overflow
+    const convertedBelowMinFee: string[] = (belowMinFee || []).map(([uid]) => {
+      const txid = this.uidMap.get(uid);
+      if (txid !== undefined) {
+        return txid;
+      } else {
+        throw new Error('GBT returned a transaction below the minimum feerate with unknown uid');
+      }
+    });
+    return { blocks: convertedBlocks, blockWeights, rates: convertedRates, clusters: convertedClusters, overflow: convertedOverflow, belowMinFee: convertedBelowMinFee };
   }
 
   public compressTx(tx: TransactionClassified): TransactionCompressed {
@@ -1,6 +1,6 @@
 use crate::{
     u32_hasher_types::{u32hashset_new, U32HasherState},
-    ThreadTransaction, thread_acceleration::ThreadAcceleration,
+    ThreadTransaction,
 };
 use std::{
     cmp::Ordering,
@@ -37,6 +37,11 @@ pub struct AuditTransaction {
     /// whether this transaction has been moved to the "modified" priority queue
     pub modified: bool,
     pub dirty: bool,
+    /// the index of the first projected block this transaction and its ancestors can be mined in
+    pub min_block: usize,
+    /// the index of the first projected block the timelocks of this transaction
+    /// and its ancestors allow, ignoring propagation
+    pub timelock_block: usize,
 }
 
 impl Hash for AuditTransaction {
@@ -71,10 +76,7 @@ pub fn partial_cmp_uid_score(a: (u32, u32, f64), b: (u32, u32, f64)) -> Option<O
 
 impl PartialOrd for AuditTransaction {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        partial_cmp_uid_score(
-            (self.uid, self.order, self.score),
-            (other.uid, other.order, other.score),
-        )
+        Some(self.cmp(other))
     }
 }
 
@@ -83,7 +85,11 @@ impl Ord for AuditTransaction {
         // Safety: The only possible values for score are f64
         // that are not NaN. This is because outside code can not
         // freely assign score. Also, calc_new_score guarantees no NaN.
-        self.partial_cmp(other).expect("score will never be NaN")
+        partial_cmp_uid_score(
+            (self.uid, self.order, self.score),
+            (other.uid, other.order, other.score),
+        )
+        .expect("score will never be NaN")
     }
 }
 
@@ -93,11 +99,7 @@ fn calc_fee_rate(fee: u64, vsize: f64) -> f64 {
 }
 
 impl AuditTransaction {
-    pub fn from_thread_transaction(tx: &ThreadTransaction, maybe_acceleration: Option<Option<&ThreadAcceleration>>) -> Self {
-        let fee_delta = match maybe_acceleration {
-            Some(Some(acceleration)) => acceleration.delta,
-            _ => 0.0
-        };
+    pub fn from_thread_transaction(tx: &ThreadTransaction, fee_delta: f64, force_dirty: bool) -> Self {
         let fee = (tx.fee as u64) + (fee_delta as u64);
         // rounded up to the nearest integer
         let is_adjusted = tx.weight < (tx.sigops * 20);
@@ -130,7 +132,9 @@ impl AuditTransaction {
             score: 0.0,
             used: false,
             modified: false,
-            dirty: effective_fee_per_vsize != tx.effective_fee_per_vsize || fee_delta > 0.0,
+            dirty: effective_fee_per_vsize != tx.effective_fee_per_vsize || force_dirty,
+            min_block: 0,
+            timelock_block: 0,
         }
     }
 
@@ -0,0 +1,364 @@
+use napi_derive::napi;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+use crate::{mempool::Mempool, u32_hasher_types::u32hashmap_with_capacity};
+
+/// Blocks between each halving of the block subsidy
+const HALVING_INTERVAL: u32 = 210_000;
+/// The subsidy of the first halving epoch, in sats
+const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+/// The BIP141 witness commitment header: `OP_RETURN`, a 36 byte push, then `0xaa21a9ed`
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+/// The coinbase witness reserved value, which the witness commitment commits to
+const WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+/// The consensus bounds on the size of the coinbase scriptSig, in bytes
+const COINBASE_SCRIPT_SIZE: std::ops::RangeInclusive<usize> = 2..=100;
+
+/// The full data of a transaction to include in an assembled block.
+///
+///   uid: the mempool transaction
+///  txid: hex txid, in the usual reversed display order
+/// wtxid: hex wtxid in display order, defaults to the txid for non-witness transactions
+///  data: the hex serialized transaction, including any witness data
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct TemplateTransaction {
+    pub uid: u32,
+    pub txid: String,
+    pub wtxid: Option<String>,
+    pub data: String,
+}
+
+/// A complete block template, ready for a miner to add the header.
+///
+///           coinbase: the hex serialized coinbase transaction, including its witness
+///      coinbase_txid: the coinbase txid, in display order
+///     coinbase_value: the block subsidy plus the fees of every transaction, in sats
+///               fees: the fees of every transaction, excluding any accelerations, in sats
+/// witness_commitment: the hex BIP141 witness commitment output script
+///        merkle_root: the hex merkle root in header byte order, the reverse of display order
+///               body: the hex serialized block following the header: the transaction count,
+///                     then the coinbase and every transaction in block order
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct BlockTemplate {
+    pub coinbase: String,
+    pub coinbase_txid: String,
+    pub coinbase_value: f64,
+    pub fees: f64,
+    pub witness_commitment: String,
+    pub merkle_root: String,
+    pub body: String,
+}
+
+/// The block subsidy at `height` from the halving schedule, in sats
+pub const fn subsidy(height: u32) -> u64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= 64 {
+        0
+    } else {
+        INITIAL_SUBSIDY >> halvings
+    }
+}
+
+/// Assemble a block at `height` from the projected `block` of mempool uids.
+///
+/// `coinbase_script` is the hex coinbase scriptSig, which must start with the BIP34 height
+/// and be 2 to 100 bytes long, and `payout_script` the hex scriptPubKey paid the coinbase value.
+/// Every uid in the block needs an entry in `transactions`.
+pub fn assemble(
+    mempool: &Mempool,
+    block: &[u32],
+    transactions: &[TemplateTransaction],
+    height: u32,
+    coinbase_script: &str,
+    payout_script: &str,
+) -> Result<BlockTemplate, String> {
+    let coinbase_script = decode_hex(coinbase_script)?;
+    if !COINBASE_SCRIPT_SIZE.contains(&coinbase_script.len()) {
+        return Err(format!(
+            "the coinbase script is {} bytes, outside the 2 to 100 allowed",
+            coinbase_script.len()
+        ));
+    }
+    if !coinbase_script.starts_with(&bip34_height(height)) {
+        return Err(format!(
+            "the coinbase script does not start with the BIP34 height {height}"
+        ));
+    }
+
+    let mut fees: u64 = 0;
+    let mut txids: Vec<[u8; 32]> = Vec::with_capacity(block.len() + 1);
+    // the coinbase wtxid is always zero
+    let mut wtxids: Vec<[u8; 32]> = vec![[0; 32]];
+    let mut body: Vec<u8> = Vec::new();
+    let mut template_txs = u32hashmap_with_capacity(transactions.len());
+    for template_tx in transactions {
+        template_txs.insert(template_tx.uid, template_tx);
+    }
+    for uid in block {
+        let tx = mempool
+            .get(*uid)
+            .ok_or_else(|| format!("transaction {uid} is not in the mempool"))?;
+        let template_tx = template_txs
+            .get(uid)
+            .ok_or_else(|| format!("missing data for transaction {uid}"))?;
+        fees += tx.fee as u64;
+        txids.push(decode_hash(&template_tx.txid)?);
+        wtxids.push(decode_hash(
+            template_tx.wtxid.as_deref().unwrap_or(&template_tx.txid),
+        )?);
+        body.extend(decode_hex(&template_tx.data)?);
+    }
+
+    let coinbase_value = subsidy(height) + fees;
+    let mut witness_commitment = WITNESS_COMMITMENT_HEADER.to_vec();
+    witness_commitment.extend(sha256d(
+        &[merkle_root(wtxids), WITNESS_RESERVED_VALUE].concat(),
+    ));
+    let (coinbase, coinbase_txid) = coinbase_transaction(
+        &coinbase_script,
+        &decode_hex(payout_script)?,
+        coinbase_value,
+        &witness_commitment,
+    );
+    txids.insert(0, coinbase_txid);
+
+    let mut serialized = Vec::with_capacity(coinbase.len() + body.len() + 9);
+    write_compact_size(&mut serialized, txids.len() as u64);
+    serialized.extend(&coinbase);
+    serialized.extend(body);
+
+    let mut display_txid = coinbase_txid;
+    display_txid.reverse();
+    Ok(BlockTemplate {
+        coinbase: encode_hex(&coinbase),
+        coinbase_txid: encode_hex(&display_txid),
+        coinbase_value: coinbase_value as f64,
+        fees: fees as f64,
+        witness_commitment: encode_hex(&witness_commitment),
+        merkle_root: encode_hex(&merkle_root(txids)),
+        body: encode_hex(&serialized),
+    })
+}
+
+// The height as Core pushes it onto the coinbase script: an OP_0 or OP_1 to OP_16 opcode
+// for small heights, otherwise a push of its minimal little-endian script number encoding
+fn bip34_height(height: u32) -> Vec<u8> {
+    match height {
+        0 => vec![0x00],
+        1..=16 => vec![0x50 + height as u8],
+        _ => {
+            let mut number: Vec<u8> = height.to_le_bytes().to_vec();
+            while number.last() == Some(&0) {
+                number.pop();
+            }
+            // a set high bit would make the number negative
+            if number.last().is_some_and(|byte| byte & 0x80 != 0) {
+                number.push(0);
+            }
+            let mut push = vec![number.len() as u8];
+            push.extend(number);
+            push
+        }
+    }
+}
+
+// Serializes a segwit coinbase with a single input, the payout output and the witness
+// commitment output. Returns the serialized transaction and its txid in internal byte order.
+fn coinbase_transaction(
+    coinbase_script: &[u8],
+    payout_script: &[u8],
+    value: u64,
+    witness_commitment: &[u8],
+) -> (Vec<u8>, [u8; 32]) {
+    let mut inputs: Vec<u8> = vec![1];
+    inputs.extend([0; 32]);
+    inputs.extend(u32::MAX.to_le_bytes());
+    write_compact_size(&mut inputs, coinbase_script.len() as u64);
+    inputs.extend(coinbase_script);
+    inputs.extend(u32::MAX.to_le_bytes());
+
+    let mut outputs: Vec<u8> = vec![2];
+    for (value, script) in [(value, payout_script), (0, witness_commitment)] {
+        outputs.extend(value.to_le_bytes());
+        write_compact_size(&mut outputs, script.len() as u64);
+        outputs.extend(script);
+    }
+
+    let version = 2u32.to_le_bytes();
+    let locktime = 0u32.to_le_bytes();
+    let stripped = [&version[..], &inputs, &outputs, &locktime].concat();
+
+    let mut witness: Vec<u8> = vec![1, 32];
+    witness.extend(WITNESS_RESERVED_VALUE);
+    let full = [
+        &version[..],
+        &[0, 1],
+        &inputs,
+        &outputs,
+        &witness,
+        &locktime,
+    ]
+    .concat();
+    (full, sha256d(&stripped))
+}
+
+// The merkle root of hashes in internal byte order, duplicating the last hash of odd levels
+fn merkle_root(mut hashes: Vec<[u8; 32]>) -> [u8; 32] {
+    while hashes.len() > 1 {
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| sha256d(&[pair[0], *pair.last().unwrap_or(&pair[0])].concat()))
+            .collect();
+    }
+    hashes.first().copied().unwrap_or([0; 32])
+}
+
+fn sha256d(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+fn write_compact_size(buffer: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0xfc => buffer.push(n as u8),
+        0xfd..=0xffff => {
+            buffer.push(0xfd);
+            buffer.extend((n as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buffer.push(0xfe);
+            buffer.extend((n as u32).to_le_bytes());
+        }
+        _ => {
+            buffer.push(0xff);
+            buffer.extend(n.to_le_bytes());
+        }
+    }
+}
+
+// a display order hex hash, in internal byte order
+fn decode_hash(hex: &str) -> Result<[u8; 32], String> {
+    let mut hash: [u8; 32] = decode_hex(hex)?
+        .try_into()
+        .map_err(|_| format!("{hex} is not a 32 byte hash"))?;
+    hash.reverse();
+    Ok(hash)
+}
+
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err(format!("odd length hex string {hex}"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("invalid hex string {hex}"))
+        })
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assemble, bip34_height, decode_hash, encode_hex, merkle_root, subsidy, TemplateTransaction,
+    };
+    use crate::{mempool::Mempool, thread_transaction::test_tx};
+
+    #[test]
+    fn test_subsidy() {
+        assert_eq!(subsidy(0), 5_000_000_000);
+        assert_eq!(subsidy(209_999), 5_000_000_000);
+        assert_eq!(subsidy(210_000), 2_500_000_000);
+        assert_eq!(subsidy(840_000), 312_500_000);
+        assert_eq!(subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        // block 100000
+        let txids = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ];
+        let mut root = merkle_root(
+            txids
+                .iter()
+                .map(|txid| decode_hash(txid).expect("valid hash"))
+                .collect(),
+        );
+        root.reverse();
+        assert_eq!(
+            encode_hex(&root),
+            "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766"
+        );
+    }
+
+    #[test]
+    fn test_assemble() {
+        let mut mempool = Mempool::with_capacity(1);
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        let transactions = vec![TemplateTransaction {
+            uid: 0,
+            txid: "11".repeat(32),
+            wtxid: None,
+            data: "0200".to_string(),
+        }];
+
+        let template = assemble(&mempool, &[0], &transactions, 840_000, "0340d10c", "51")
+            .expect("valid template");
+        assert_eq!(template.coinbase_value, 312_501_000.0);
+        assert_eq!(
+            template.witness_commitment,
+            "6a24aa21a9eda47566b1f71f4d19f6adc25d0fad3cccd671b505ace246b583ab110f7aa7995d"
+        );
+        assert_eq!(
+            template.coinbase_txid,
+            "dd756441d86b164ffde2ec2a0cb3788edd32e13008a6c506f92318ae35d39f7e"
+        );
+        assert_eq!(
+            template.merkle_root,
+            "d13b444eabf3a76d29a459c886024adf110d7ad1316be621e30f13d403986dac"
+        );
+        assert!(template.coinbase.starts_with("020000000001010000"));
+        // two transactions, then the coinbase, then the raw transaction
+        assert!(template
+            .body
+            .starts_with(&format!("02{}", template.coinbase)));
+        assert!(template.body.ends_with("0200"));
+
+        assert!(assemble(&mempool, &[1], &transactions, 840_000, "0340d10c", "51").is_err());
+        assert!(assemble(&mempool, &[0], &transactions, 840_000, "zz", "51").is_err());
+
+        // the coinbase script must start with the height, and be 2 to 100 bytes long
+        let coinbase =
+            |script: &str| assemble(&mempool, &[0], &transactions, 840_000, script, "51");
+        assert!(coinbase("0341d10c").is_err());
+        assert!(coinbase("").is_err());
+        assert!(coinbase(&format!("0340d10c{}", "00".repeat(96))).is_ok());
+        assert!(coinbase(&format!("0340d10c{}", "00".repeat(97))).is_err());
+    }
+
+    #[test]
+    fn test_bip34_height() {
+        assert_eq!(encode_hex(&bip34_height(0)), "00");
+        assert_eq!(encode_hex(&bip34_height(16)), "60");
+        assert_eq!(encode_hex(&bip34_height(17)), "0111");
+        assert_eq!(encode_hex(&bip34_height(128)), "028000");
+        assert_eq!(encode_hex(&bip34_height(840_000)), "0340d10c");
+    }
+}
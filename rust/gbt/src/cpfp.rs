@@ -0,0 +1,267 @@
+use napi::{bindgen_prelude::Result, Env, Task};
+use napi_derive::napi;
+use tracing::{info, trace};
+
+use crate::{
+    gbt::{self, GbtContext},
+    mempool::Mempool,
+    thread_acceleration::{fee_deltas, FeeDeltas, ThreadAcceleration},
+    thread_transaction::ThreadTransaction,
+    u32_hasher_types::u32hashmap_with_capacity,
+    Chunk, GbtOptions, ThreadTransactionsMap,
+};
+
+/// The result from calling the `block_cpfp` function.
+///
+/// This contains the following:
+///    rates: A Vector of tuples containing transaction IDs (u32) and effective fee per vsize (f64)
+///           for every transaction in the block
+/// clusters: A 2D Vector of transaction IDs representing clusters of dependent transactions,
+///           ordered by ascending ancestor count (so the root of each cluster is the last element)
+#[napi(constructor)]
+pub struct BlockCpfpResult {
+    pub rates: Vec<Vec<f64>>, // Tuples not supported. u32 fits inside f64
+    pub clusters: Vec<Vec<u32>>,
+}
+
+/// Runs `block_cpfp` on the libuv thread pool.
+pub struct BlockCpfpTask {
+    pub transactions: Vec<ThreadTransaction>,
+    pub accelerations: Vec<ThreadAcceleration>,
+}
+
+impl Task for BlockCpfpTask {
+    type Output = BlockCpfpResult;
+    type JsValue = BlockCpfpResult;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        Ok(block_cpfp(
+            std::mem::take(&mut self.transactions),
+            &self.accelerations,
+        ))
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Calculate effective fee rates and CPFP clusters for the transactions in an already-mined block.
+///
+/// Runs the same selection loop as `gbt::gbt` over the block's transactions with a single
+/// unbounded block, so every transaction is assigned the feerate of the package it was mined in.
+/// Replaces `calculateGoodBlockCpfp` and `makeBlockTemplate` from the mempool backend's
+/// [cpfp.ts](https://github.com/mempool/mempool/blob/master/backend/src/api/cpfp.ts).
+pub fn block_cpfp(
+    transactions: Vec<ThreadTransaction>,
+    accelerations: &[ThreadAcceleration],
+) -> BlockCpfpResult {
+    let max_uid = transactions.iter().map(|tx| tx.uid).max().unwrap_or(0) as usize;
+    let mut block: ThreadTransactionsMap = u32hashmap_with_capacity(transactions.len());
+    for tx in transactions {
+        block.insert(tx.uid, tx);
+    }
+
+    info!("Calculating block cpfp for {} transactions", block.len());
+    let fee_deltas = fee_deltas(accelerations);
+    let result = gbt::gbt(
+        &mut block,
+        &GbtContext::new(&fee_deltas),
+        max_uid,
+        u32::MAX,
+        1,
+        &GbtOptions::default(),
+    );
+
+    // gbt only reports rates which changed, but confirmed transactions need all of them
+    let rates: Vec<Vec<f64>> = block
+        .values()
+        .map(|tx| vec![f64::from(tx.uid), tx.effective_fee_per_vsize])
+        .collect();
+    trace!("block cpfp rates: {:#?}", rates);
+
+    BlockCpfpResult {
+        rates,
+        clusters: result.clusters,
+    }
+}
+
+/// CPFP data for a single mempool transaction, calculated from its whole cluster.
+///
+///                      ancestors: uids of all in-mempool ancestors
+///                    descendants: uids of all in-mempool descendants
+///                best_descendant: uid of the descendant whose ancestor package has the highest
+///                                 feerate, if any
+///                         chunks: every package in the cluster, in selection order
+///        effective_fee_per_vsize: the feerate of the package this transaction is selected in
+#[napi(object)]
+pub struct CpfpInfo {
+    pub ancestors: Vec<u32>,
+    pub descendants: Vec<u32>,
+    pub best_descendant: Option<u32>,
+    pub chunks: Vec<Chunk>,
+    pub effective_fee_per_vsize: f64,
+}
+
+/// Calculate the CPFP data for one transaction straight from the mempool graph.
+///
+/// Replaces the relatives graph built on demand by `calculateMempoolTxCpfp` in the mempool
+/// backend's [cpfp.ts](https://github.com/mempool/mempool/blob/master/backend/src/api/cpfp.ts),
+/// without the `MAX_RELATIVE_GRAPH_SIZE` cap.
+pub fn cpfp_info(
+    mempool: &Mempool,
+    fee_deltas: &FeeDeltas,
+    uid: u32,
+) -> Option<CpfpInfo> {
+    if !mempool.contains(uid) {
+        return None;
+    }
+
+    let (mut cluster, max_uid) = mempool.subset(mempool.cluster(uid));
+
+    trace!(
+        "Linearizing cluster of {} transactions around {}",
+        cluster.len(),
+        uid
+    );
+    let result = gbt::gbt(
+        &mut cluster,
+        &GbtContext::new(fee_deltas),
+        max_uid,
+        u32::MAX,
+        1,
+        &GbtOptions {
+            chunks: Some(true),
+            ..GbtOptions::default()
+        },
+    );
+
+    let package_rate = |root: u32| -> f64 {
+        let mut fee: f64 = 0.0;
+        let mut vsize: u32 = 0;
+        for member in mempool
+            .ancestors(root)
+            .into_iter()
+            .chain(std::iter::once(root))
+        {
+            if let Some(tx) = cluster.get(&member) {
+                fee += tx.fee + fee_deltas.get(&member).copied().unwrap_or(0.0);
+                vsize += tx.sigop_adjusted_vsize();
+            }
+        }
+        fee / f64::from(vsize.max(1))
+    };
+
+    let mut ancestors: Vec<u32> = mempool.ancestors(uid).into_iter().collect();
+    ancestors.sort_unstable();
+    let mut descendants: Vec<u32> = mempool.descendants(uid).into_iter().collect();
+    descendants.sort_unstable();
+    let best_descendant = descendants
+        .iter()
+        .map(|descendant| (*descendant, package_rate(*descendant)))
+        // ties go to the lowest uid, since descendants are sorted
+        .fold(
+            None,
+            |best: Option<(u32, f64)>, (descendant, rate)| match best {
+                Some((_, best_rate)) if best_rate >= rate => best,
+                _ => Some((descendant, rate)),
+            },
+        )
+        .map(|(descendant, _)| descendant);
+
+    Some(CpfpInfo {
+        ancestors,
+        descendants,
+        best_descendant,
+        chunks: result.chunks.unwrap_or_default(),
+        effective_fee_per_vsize: cluster
+            .get(&uid)
+            .map_or(0.0, |tx| tx.effective_fee_per_vsize),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_cpfp, cpfp_info};
+    use crate::{
+        mempool::Mempool,
+        thread_acceleration::{FeeDeltas, ThreadAcceleration},
+        thread_transaction::test_tx as tx,
+        u32_hasher_types::u32hashmap_with_capacity,
+    };
+
+    fn rate_of(rates: &[Vec<f64>], uid: u32) -> f64 {
+        rates
+            .iter()
+            .find(|rate| rate[0] == f64::from(uid))
+            .map(|rate| rate[1])
+            .expect("every transaction has a rate")
+    }
+
+    #[test]
+    fn test_child_pays_for_parent() {
+        let result = block_cpfp(
+            vec![
+                tx(0, 100.0, 400, vec![]),
+                tx(1, 1900.0, 400, vec![0]),
+                tx(2, 500.0, 400, vec![]),
+            ],
+            &[],
+        );
+
+        assert_eq!(result.rates.len(), 3);
+        assert_eq!(rate_of(&result.rates, 0), 10.0);
+        assert_eq!(rate_of(&result.rates, 1), 10.0);
+        assert_eq!(rate_of(&result.rates, 2), 5.0);
+        assert_eq!(result.clusters, vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn test_accelerated_transaction() {
+        let result = block_cpfp(
+            vec![tx(0, 100.0, 400, vec![]), tx(1, 100.0, 400, vec![0])],
+            &[ThreadAcceleration {
+                uid: 0,
+                delta: 900.0,
+            }],
+        );
+
+        assert_eq!(rate_of(&result.rates, 0), 10.0);
+        assert_eq!(rate_of(&result.rates, 1), 1.0);
+        assert!(result.clusters.is_empty());
+    }
+
+    #[test]
+    fn test_cpfp_info() {
+        let mut mempool = Mempool::with_capacity(4);
+        mempool.insert(tx(0, 100.0, 400, vec![]));
+        mempool.insert(tx(1, 1900.0, 400, vec![0]));
+        mempool.insert(tx(2, 300.0, 400, vec![0]));
+        mempool.insert(tx(3, 5000.0, 400, vec![]));
+        let no_deltas: FeeDeltas = u32hashmap_with_capacity(0);
+
+        let info = cpfp_info(&mempool, &no_deltas, 0).expect("transaction is in the mempool");
+        assert!(info.ancestors.is_empty());
+        assert_eq!(info.descendants, vec![1, 2]);
+        assert_eq!(info.best_descendant, Some(1));
+        assert_eq!(info.effective_fee_per_vsize, 10.0);
+        let chunks: Vec<(Vec<u32>, f64)> = info
+            .chunks
+            .into_iter()
+            .map(|chunk| (chunk.uids, chunk.effective_fee_per_vsize))
+            .collect();
+        assert_eq!(chunks, vec![(vec![0, 1], 10.0), (vec![2], 3.0)]);
+
+        let info = cpfp_info(&mempool, &no_deltas, 2).expect("transaction is in the mempool");
+        assert_eq!(info.ancestors, vec![0]);
+        assert!(info.descendants.is_empty());
+        assert_eq!(info.best_descendant, None);
+        assert_eq!(info.effective_fee_per_vsize, 3.0);
+
+        mempool.remove(0);
+        assert!(cpfp_info(&mempool, &no_deltas, 0).is_none());
+        let info = cpfp_info(&mempool, &no_deltas, 1).expect("transaction is in the mempool");
+        assert!(info.ancestors.is_empty());
+        assert_eq!(info.effective_fee_per_vsize, 19.0);
+    }
+}
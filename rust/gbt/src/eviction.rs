@@ -0,0 +1,199 @@
+use napi_derive::napi;
+use priority_queue::PriorityQueue;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+use tracing::{debug, info};
+
+use crate::{
+    mempool::Mempool,
+    rbf::DEFAULT_INCREMENTAL_RELAY_FEE_RATE,
+    thread_acceleration::FeeDeltas,
+    u32_hasher_types::{
+        u32hashmap_with_capacity, u32hashset_new, u32priority_queue_with_capacity, U32HasherState,
+    },
+};
+
+/// The result of trimming the mempool down to a size limit.
+///
+///          evicted: uids of the evicted transactions, in eviction order
+///     min_fee_rate: the rolling minimum feerate after trimming, in sats/vB, or 0 if nothing
+///                   was evicted
+///            vsize: total sigop-adjusted vsize of the remaining mempool
+#[napi(object)]
+pub struct EvictionResult {
+    pub evicted: Vec<u32>,
+    pub min_fee_rate: f64,
+    pub vsize: f64,
+}
+
+/// Lowest descendant score first, then the highest uid (usually the newest transaction)
+#[derive(Debug, PartialEq)]
+struct EvictionPriority {
+    uid: u32,
+    score: f64,
+}
+impl Eq for EvictionPriority {}
+impl PartialOrd for EvictionPriority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for EvictionPriority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .partial_cmp(&self.score)
+            .expect("score will never be NaN")
+            .then_with(|| self.uid.cmp(&other.uid))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Package {
+    fee: f64,
+    vsize: u32,
+}
+
+impl Package {
+    fn rate(self) -> f64 {
+        self.fee / f64::from(self.vsize.max(1))
+    }
+}
+
+/// Simulate trimming the mempool down to `size_limit` vbytes the way Core's `TrimToSize` does.
+///
+/// Repeatedly evicts the transaction with the lowest descendant score along with all of its
+/// descendants, and raises the rolling minimum fee to the feerate of each evicted package
+/// plus the incremental relay feerate. Core limits the memory usage of the mempool rather
+/// than its vsize, so this is only an approximation of when eviction starts.
+pub fn trim_to_size(mempool: &Mempool, fee_deltas: &FeeDeltas, size_limit: f64) -> EvictionResult {
+    let mut own: HashMap<u32, Package, U32HasherState> = u32hashmap_with_capacity(mempool.len());
+    for (uid, tx) in &mempool.transactions {
+        own.insert(
+            *uid,
+            Package {
+                fee: tx.fee + fee_deltas.get(uid).copied().unwrap_or(0.0),
+                vsize: tx.sigop_adjusted_vsize(),
+            },
+        );
+    }
+    let mut total_vsize: u64 = own.values().map(|package| u64::from(package.vsize)).sum();
+    let size_limit = size_limit as u64;
+
+    // the fee and vsize of each transaction together with all of its descendants
+    let mut descendant_packages = u32hashmap_with_capacity(mempool.len());
+    let mut queue: PriorityQueue<u32, EvictionPriority, U32HasherState> =
+        u32priority_queue_with_capacity(mempool.len());
+    for uid in mempool.transactions.keys() {
+        let mut package = own[uid];
+        for descendant in mempool.descendants(*uid) {
+            if let Some(descendant) = own.get(&descendant) {
+                package.fee += descendant.fee;
+                package.vsize += descendant.vsize;
+            }
+        }
+        descendant_packages.insert(*uid, package);
+        queue.push(*uid, descendant_score(own[uid], package, *uid));
+    }
+
+    info!(
+        "Trimming {} vbytes of mempool to {} vbytes",
+        total_vsize, size_limit
+    );
+    let mut evicted: Vec<u32> = Vec::new();
+    let mut removed: HashSet<u32, U32HasherState> = u32hashset_new();
+    let mut min_fee_rate: f64 = 0.0;
+    while total_vsize > size_limit {
+        let Some((uid, _)) = queue.pop() else {
+            break;
+        };
+        min_fee_rate =
+            min_fee_rate.max(descendant_packages[&uid].rate() + DEFAULT_INCREMENTAL_RELAY_FEE_RATE);
+
+        let mut package: Vec<u32> = mempool
+            .descendants(uid)
+            .into_iter()
+            .filter(|descendant| !removed.contains(descendant))
+            .collect();
+        package.sort_unstable();
+        package.insert(0, uid);
+        debug!("Evicting package of {} rooted at {}", package.len(), uid);
+
+        for member in &package {
+            removed.insert(*member);
+            queue.remove(member);
+        }
+        for member in &package {
+            let member_package = own[member];
+            total_vsize -= u64::from(member_package.vsize);
+            // the remaining ancestors lose this descendant
+            for ancestor in mempool.ancestors(*member) {
+                if removed.contains(&ancestor) {
+                    continue;
+                }
+                if let Some(ancestor_package) = descendant_packages.get_mut(&ancestor) {
+                    ancestor_package.fee -= member_package.fee;
+                    ancestor_package.vsize -= member_package.vsize;
+                    queue.change_priority(
+                        &ancestor,
+                        descendant_score(own[&ancestor], *ancestor_package, ancestor),
+                    );
+                }
+            }
+        }
+        evicted.extend(package);
+    }
+
+    EvictionResult {
+        evicted,
+        min_fee_rate,
+        vsize: total_vsize as f64,
+    }
+}
+
+// Core sorts by the higher of the transaction's own feerate and its descendant feerate,
+// so a high feerate transaction is never evicted for the sake of its low feerate children
+fn descendant_score(own: Package, descendants: Package, uid: u32) -> EvictionPriority {
+    EvictionPriority {
+        uid,
+        score: own.rate().max(descendants.rate()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trim_to_size;
+    use crate::{
+        mempool::Mempool, thread_transaction::test_tx as tx,
+        u32_hasher_types::u32hashmap_with_capacity,
+    };
+
+    #[test]
+    fn test_trim_to_size() {
+        let mut mempool = Mempool::with_capacity(4);
+        mempool.insert(tx(0, 1000.0, 400, vec![]));
+        mempool.insert(tx(1, 100.0, 400, vec![0]));
+        mempool.insert(tx(2, 300.0, 400, vec![]));
+        mempool.insert(tx(3, 200.0, 400, vec![2]));
+        let no_deltas = u32hashmap_with_capacity(0);
+
+        let result = trim_to_size(&mempool, &no_deltas, 400.0);
+        assert!(result.evicted.is_empty());
+        assert_eq!(result.min_fee_rate, 0.0);
+        assert_eq!(result.vsize, 400.0);
+
+        // lowest descendant score first, tx 2 outscores its child
+        let result = trim_to_size(&mempool, &no_deltas, 150.0);
+        assert_eq!(result.evicted, vec![1, 3, 2]);
+        assert_eq!(result.min_fee_rate, 4.0);
+        assert_eq!(result.vsize, 100.0);
+
+        let mut deltas = u32hashmap_with_capacity(1);
+        deltas.insert(1, 2000.0);
+        let result = trim_to_size(&mempool, &deltas, 300.0);
+        assert_eq!(result.evicted, vec![3]);
+        assert_eq!(result.min_fee_rate, 3.0);
+    }
+}
@@ -0,0 +1,143 @@
+use napi_derive::napi;
+use tracing::info;
+
+use crate::{
+    gbt,
+    mempool::Mempool,
+    rbf::{block_positions, TxPosition},
+    thread_acceleration::FeeDeltas,
+    GbtOptions,
+};
+
+/// Core's default `-mempoolexpiry` of 336 hours, in seconds
+pub const DEFAULT_MEMPOOL_EXPIRY: f64 = 336.0 * 60.0 * 60.0;
+
+/// A transaction which will expire from the mempool within the horizon.
+///
+///             uid: the expiring transaction
+///      expires_at: when it expires, in unix seconds
+///     descendants: uids of the in-mempool descendants which expire along with it
+///       positions: projected blocks of the transaction followed by its descendants
+///       projected: whether any of them are in a projected block
+#[napi(object)]
+pub struct ExpiringTransaction {
+    pub uid: u32,
+    pub expires_at: f64,
+    pub descendants: Vec<u32>,
+    pub positions: Vec<TxPosition>,
+    pub projected: bool,
+}
+
+/// Find the transactions which Core will expire from the mempool by `now + horizon`,
+/// ordered by expiry time.
+///
+/// Only transactions with a `first_seen` time can expire. Their projected blocks come from
+/// a fresh gbt run over the whole mempool, which is only needed if anything is expiring.
+pub fn expiring(
+    mempool: &Mempool,
+    fee_deltas: &FeeDeltas,
+    now: f64,
+    horizon: f64,
+    expiry: f64,
+    max_block_weight: u32,
+    max_blocks: usize,
+) -> Vec<ExpiringTransaction> {
+    let mut expiring: Vec<(u32, f64)> = mempool
+        .transactions
+        .values()
+        .filter_map(|tx| {
+            tx.first_seen
+                .map(|first_seen| (tx.uid, first_seen + expiry))
+        })
+        .filter(|(_, expires_at)| *expires_at <= now + horizon)
+        .collect();
+    if expiring.is_empty() {
+        return Vec::new();
+    }
+    expiring.sort_unstable_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+    info!(
+        "{} transactions expire within {} seconds",
+        expiring.len(),
+        horizon
+    );
+
+    let result = gbt::project(
+        mempool,
+        fee_deltas,
+        max_block_weight,
+        max_blocks,
+        &GbtOptions::default(),
+    );
+
+    expiring
+        .into_iter()
+        .map(|(uid, expires_at)| {
+            let mut descendants: Vec<u32> = mempool.descendants(uid).into_iter().collect();
+            descendants.sort_unstable();
+            let uids: Vec<u32> = std::iter::once(uid)
+                .chain(descendants.iter().copied())
+                .collect();
+            let positions = block_positions(&result, &uids);
+            ExpiringTransaction {
+                uid,
+                expires_at,
+                descendants,
+                projected: positions.iter().any(|position| position.block.is_some()),
+                positions,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expiring, DEFAULT_MEMPOOL_EXPIRY};
+    use crate::{
+        mempool::Mempool,
+        thread_transaction::{test_tx, ThreadTransaction},
+        u32_hasher_types::u32hashmap_with_capacity,
+    };
+
+    #[test]
+    fn test_expiring() {
+        let mut mempool = Mempool::with_capacity(4);
+        for (uid, inputs, first_seen) in [
+            (0, vec![], Some(1000.0)),
+            (1, vec![0], Some(5000.0)),
+            (2, vec![], Some(2000.0)),
+            (3, vec![], None),
+        ] {
+            mempool.insert(ThreadTransaction {
+                first_seen,
+                ..test_tx(uid, 1000.0, 400, inputs)
+            });
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+        let now = DEFAULT_MEMPOOL_EXPIRY;
+
+        let expiring_within = |horizon: f64| {
+            expiring(
+                &mempool,
+                &no_deltas,
+                now,
+                horizon,
+                DEFAULT_MEMPOOL_EXPIRY,
+                4_000_000,
+                8,
+            )
+        };
+
+        let result = expiring_within(1000.0);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].uid, 0);
+        assert_eq!(result[0].expires_at, now + 1000.0);
+        assert_eq!(result[0].descendants, vec![1]);
+        assert!(result[0].projected);
+        assert_eq!(result[0].positions[1].block, Some(0));
+
+        let uids: Vec<u32> = expiring_within(5000.0).iter().map(|tx| tx.uid).collect();
+        assert_eq!(uids, vec![0, 2, 1]);
+
+        assert!(expiring_within(0.0).is_empty());
+    }
+}
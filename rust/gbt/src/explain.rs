@@ -0,0 +1,255 @@
+use napi_derive::napi;
+
+use crate::{
+    audit_transaction::AuditTransaction,
+    gbt,
+    mempool::Mempool,
+    thread_acceleration::FeeDeltas,
+    Chunk, GbtOptions,
+};
+
+/// What one step of the selection loop did with the explained transaction.
+#[derive(Debug, PartialEq, Eq)]
+#[napi(string_enum = "kebab-case")]
+pub enum ExplainOutcome {
+    /// Its own package was selected into the block
+    Selected,
+    /// It was selected as an ancestor in the package of one of its descendants
+    SelectedAsAncestor,
+    /// Its package did not fit in the block, so it was held in the overflow
+    Overflow,
+    /// Its package was held in the overflow until its timelocks or propagation allow it
+    Deferred,
+    /// Its package paid less than the generator's minimum feerate
+    BelowMinFee,
+}
+
+/// One time the selection loop reached the explained transaction.
+///
+///        block: index of the projected block being built
+///      package: the package considered, ordered by uid, including the explained transaction
+///        score: the ancestor score the package was ranked by, in sats/vB
+/// package_rate: the effective feerate of the package, in sats/vB
+///      outcome: what happened to the package
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct ExplainStep {
+    pub block: u32,
+    pub package: Vec<u32>,
+    pub score: f64,
+    pub package_rate: f64,
+    pub outcome: ExplainOutcome,
+}
+
+/// A trace of how gbt selected, or failed to select, one transaction.
+///
+///           uid: the explained transaction
+///         block: the projected block it was selected into, if any
+///    overflowed: whether it was ever held in the overflow list
+///         steps: every time its package was considered, in selection order
+///         ahead: the packages selected into each earlier block, in selection order,
+///                or into every block if it was never selected
+/// block_cutoffs: the lowest package score selected into each block, as in
+///                `BlockScores.min_accepted`, or 0 for empty blocks
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct Explanation {
+    pub uid: u32,
+    pub block: Option<u32>,
+    pub overflowed: bool,
+    pub steps: Vec<ExplainStep>,
+    pub ahead: Vec<Vec<Chunk>>,
+    pub block_cutoffs: Vec<f64>,
+}
+
+/// Records the events of a gbt run which concern a single transaction.
+pub struct Tracer {
+    uid: u32,
+    block: Option<usize>,
+    overflowed: bool,
+    steps: Vec<ExplainStep>,
+    ahead: Vec<Vec<Chunk>>,
+    block_cutoffs: Vec<f64>,
+}
+
+impl Tracer {
+    pub fn new(uid: u32) -> Self {
+        Self {
+            uid,
+            block: None,
+            overflowed: false,
+            steps: Vec::new(),
+            ahead: vec![Vec::new()],
+            block_cutoffs: Vec::new(),
+        }
+    }
+
+    /// Record a package which was popped from the queues but not selected
+    pub fn skipped(&mut self, tx: &AuditTransaction, block: usize, outcome: ExplainOutcome) {
+        if tx.uid != self.uid {
+            return;
+        }
+        self.overflowed |= matches!(outcome, ExplainOutcome::Overflow | ExplainOutcome::Deferred);
+        let mut package: Vec<u32> = tx.ancestors.iter().copied().collect();
+        package.push(tx.uid);
+        package.sort_unstable();
+        self.steps.push(ExplainStep {
+            block: block as u32,
+            package,
+            score: tx.score(),
+            package_rate: tx.cluster_rate(),
+            outcome,
+        });
+    }
+
+    /// Record a package rooted at `root` being selected into `block`
+    pub fn selected(&mut self, chunk: Chunk, root: u32, score: f64, block: usize) {
+        if chunk.uids.contains(&self.uid) {
+            let mut package = chunk.uids;
+            package.sort_unstable();
+            self.block = Some(block);
+            self.steps.push(ExplainStep {
+                block: block as u32,
+                package,
+                score,
+                package_rate: chunk.effective_fee_per_vsize,
+                outcome: if root == self.uid {
+                    ExplainOutcome::Selected
+                } else {
+                    ExplainOutcome::SelectedAsAncestor
+                },
+            });
+        } else if self.block.is_none() {
+            if let Some(ahead) = self.ahead.last_mut() {
+                ahead.push(chunk);
+            }
+        }
+    }
+
+    /// Record the end of the block being built, with the lowest score selected into it
+    pub fn block_finished(&mut self, min_accepted: Option<f64>) {
+        self.block_cutoffs.push(min_accepted.unwrap_or(0.0));
+        if self.block.is_none() {
+            self.ahead.push(Vec::new());
+        }
+    }
+
+    pub fn finish(mut self) -> Explanation {
+        self.ahead
+            .truncate(self.block.unwrap_or(self.block_cutoffs.len()));
+        Explanation {
+            uid: self.uid,
+            block: self.block.map(|block| block as u32),
+            overflowed: self.overflowed,
+            steps: self.steps,
+            ahead: self.ahead,
+            block_cutoffs: self.block_cutoffs,
+        }
+    }
+}
+
+/// Explain how gbt would treat one transaction in a fresh run over the whole mempool,
+/// or `None` if it is not in the mempool.
+///
+/// Only the `now`, `propagation_delay` and `policy` options affect the selection.
+pub fn explain(
+    mempool: &Mempool,
+    fee_deltas: &FeeDeltas,
+    uid: u32,
+    max_block_weight: u32,
+    max_blocks: usize,
+    options: &GbtOptions,
+) -> Option<Explanation> {
+    if !mempool.transactions.contains_key(&uid) {
+        return None;
+    }
+    let result = gbt::project(
+        mempool,
+        fee_deltas,
+        max_block_weight,
+        max_blocks,
+        &GbtOptions {
+            explain: Some(uid),
+            ..options.selection_only()
+        },
+    );
+    result.explanation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, ExplainOutcome};
+    use crate::{
+        gbt, mempool::Mempool, thread_transaction::test_tx as tx,
+        u32_hasher_types::u32hashmap_with_capacity, GbtOptions,
+    };
+
+    #[test]
+    fn test_explain() {
+        let mut mempool = Mempool::with_capacity(4);
+        // a large parent paid for by its child, and a small package which fits first
+        mempool.insert(tx(0, 30_000.0, 3_000, vec![]));
+        mempool.insert(tx(1, 100_000.0, 400, vec![0]));
+        mempool.insert(tx(2, 20_000.0, 3_000, vec![]));
+        mempool.insert(tx(3, 1_000.0, 400, vec![]));
+        let no_deltas = u32hashmap_with_capacity(0);
+        let explain_uid = |uid: u32| {
+            explain(&mempool, &no_deltas, uid, 12_000, 3, &GbtOptions::default())
+                .expect("in mempool")
+        };
+
+        let explanation = explain_uid(0);
+        assert_eq!(explanation.block, Some(0));
+        assert!(!explanation.overflowed);
+        assert_eq!(explanation.steps.len(), 1);
+        assert_eq!(explanation.steps[0].package, vec![0, 1]);
+        assert_eq!(
+            explanation.steps[0].outcome,
+            ExplainOutcome::SelectedAsAncestor
+        );
+        assert!(explanation.ahead.is_empty());
+
+        // tx 2 does not fit next to package [0, 1], so tx 3 is selected ahead of it
+        let explanation = explain_uid(2);
+        assert_eq!(explanation.block, Some(1));
+        assert!(explanation.overflowed);
+        let outcomes: Vec<ExplainOutcome> =
+            explanation.steps.iter().map(|step| step.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![ExplainOutcome::Overflow, ExplainOutcome::Selected]
+        );
+        assert_eq!(explanation.ahead.len(), 1);
+        let ahead: Vec<Vec<u32>> = explanation.ahead[0]
+            .iter()
+            .map(|chunk| chunk.uids.clone())
+            .collect();
+        assert_eq!(ahead, vec![vec![0, 1], vec![3]]);
+        assert_eq!(explanation.block_cutoffs, vec![10.0, 20_000.0 / 750.0]);
+
+        // the cutoffs are the same scores as the blocks report
+        let result = gbt::project(
+            &mempool,
+            &no_deltas,
+            12_000,
+            3,
+            &GbtOptions {
+                explain: Some(2),
+                ..GbtOptions::default()
+            },
+        );
+        let min_accepted: Vec<f64> = result
+            .block_scores
+            .iter()
+            .map(|scores| scores.min_accepted.unwrap_or(0.0))
+            .collect();
+        assert_eq!(
+            result
+                .explanation
+                .map(|explanation| explanation.block_cutoffs),
+            Some(min_accepted)
+        );
+
+        assert!(explain(&mempool, &no_deltas, 4, 12_000, 3, &GbtOptions::default()).is_none());
+    }
+}
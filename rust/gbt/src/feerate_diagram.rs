@@ -0,0 +1,179 @@
+use napi_derive::napi;
+
+/// A point on a feerate diagram: the total fee available in the first `vsize` vbytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[napi(object)]
+pub struct DiagramPoint {
+    pub vsize: f64,
+    pub fee: f64,
+}
+
+/// The cumulative fee vs cumulative vsize curve of a sequence of chunks.
+///
+/// Starts at the origin, with one point at the end of each chunk in the order they were added.
+/// Fees between points are linearly interpolated, since every chunk is assumed to be
+/// divisible at its own feerate.
+#[derive(Clone, Debug)]
+pub struct FeerateDiagram {
+    points: Vec<DiagramPoint>,
+}
+
+impl Default for FeerateDiagram {
+    fn default() -> Self {
+        Self {
+            points: vec![DiagramPoint {
+                vsize: 0.0,
+                fee: 0.0,
+            }],
+        }
+    }
+}
+
+impl FeerateDiagram {
+    pub fn from_points(points: Vec<DiagramPoint>) -> Self {
+        if points.is_empty() {
+            Self::default()
+        } else {
+            Self { points }
+        }
+    }
+
+    pub fn push_chunk(&mut self, fee: f64, vsize: f64) {
+        let last = self.end();
+        self.points.push(DiagramPoint {
+            vsize: last.vsize + vsize,
+            fee: last.fee + fee,
+        });
+    }
+
+    #[inline]
+    pub fn end(&self) -> DiagramPoint {
+        // Safety: there is always at least the origin point
+        self.points[self.points.len() - 1]
+    }
+
+    /// The total fee available in the first `vsize` vbytes of the diagram.
+    ///
+    /// Saturates at the total fee of the diagram past its end.
+    pub fn fee_at(&self, vsize: f64) -> f64 {
+        let index = self.points.partition_point(|point| point.vsize < vsize);
+        match (
+            index.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(index),
+        ) {
+            (_, Some(point)) if point.vsize == vsize => point.fee,
+            (Some(prev), Some(next)) => {
+                prev.fee + (next.fee - prev.fee) * (vsize - prev.vsize) / (next.vsize - prev.vsize)
+            }
+            (Some(prev), None) => prev.fee,
+            (None, _) => 0.0,
+        }
+    }
+
+    pub fn into_points(self) -> Vec<DiagramPoint> {
+        self.points
+    }
+}
+
+// differences smaller than this are treated as ties
+const FEE_EPSILON: f64 = 1e-6;
+
+/// Finds the vsize ranges where each diagram has more fee available than the other.
+///
+/// Returns `(a_better, b_better)`, each a list of non-overlapping `[start, end]` vsize ranges.
+/// Both diagrams are treated as flat past their ends.
+pub fn dominance(a: &FeerateDiagram, b: &FeerateDiagram) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+    let mut breakpoints: Vec<f64> = a
+        .points
+        .iter()
+        .chain(b.points.iter())
+        .map(|point| point.vsize)
+        .collect();
+    breakpoints.sort_unstable_by(f64::total_cmp);
+    breakpoints.dedup();
+
+    let mut a_better: Vec<Vec<f64>> = Vec::new();
+    let mut b_better: Vec<Vec<f64>> = Vec::new();
+    let mut push_range = |difference: f64, start: f64, end: f64| {
+        let ranges = if difference > 0.0 {
+            &mut a_better
+        } else {
+            &mut b_better
+        };
+        match ranges.last_mut() {
+            Some(last) if last[1] == start => last[1] = end,
+            _ => ranges.push(vec![start, end]),
+        }
+    };
+
+    let mut prev_vsize: f64 = 0.0;
+    let mut prev_difference: f64 = 0.0;
+    for vsize in breakpoints {
+        let difference = a.fee_at(vsize) - b.fee_at(vsize);
+        let difference = if difference.abs() < FEE_EPSILON {
+            0.0
+        } else {
+            difference
+        };
+        if vsize > prev_vsize {
+            // the difference is linear between breakpoints,
+            // so it can only change sign once within this segment
+            if prev_difference * difference < 0.0 {
+                let crossover = prev_vsize
+                    + (vsize - prev_vsize) * prev_difference / (prev_difference - difference);
+                push_range(prev_difference, prev_vsize, crossover);
+                push_range(difference, crossover, vsize);
+            } else if prev_difference != 0.0 || difference != 0.0 {
+                push_range(prev_difference + difference, prev_vsize, vsize);
+            }
+        }
+        prev_vsize = vsize;
+        prev_difference = difference;
+    }
+
+    (a_better, b_better)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dominance, DiagramPoint, FeerateDiagram};
+
+    #[test]
+    fn test_fee_at() {
+        let mut diagram = FeerateDiagram::default();
+        diagram.push_chunk(1000.0, 100.0);
+        diagram.push_chunk(500.0, 100.0);
+
+        assert_eq!(
+            diagram.end(),
+            DiagramPoint {
+                vsize: 200.0,
+                fee: 1500.0
+            }
+        );
+        assert_eq!(diagram.fee_at(0.0), 0.0);
+        assert_eq!(diagram.fee_at(50.0), 500.0);
+        assert_eq!(diagram.fee_at(100.0), 1000.0);
+        assert_eq!(diagram.fee_at(150.0), 1250.0);
+        assert_eq!(diagram.fee_at(1000.0), 1500.0);
+        assert_eq!(FeerateDiagram::from_points(vec![]).fee_at(10.0), 0.0);
+    }
+
+    #[test]
+    fn test_dominance() {
+        let mut a = FeerateDiagram::default();
+        a.push_chunk(1000.0, 100.0);
+        a.push_chunk(100.0, 100.0);
+        let mut b = FeerateDiagram::default();
+        b.push_chunk(600.0, 100.0);
+        b.push_chunk(600.0, 100.0);
+
+        let (a_better, b_better) = dominance(&a, &b);
+        assert_eq!(a_better, vec![vec![0.0, 180.0]]);
+        assert_eq!(b_better, vec![vec![180.0, 200.0]]);
+
+        let (a_better, b_better) = dominance(&a, &a);
+        assert!(a_better.is_empty());
+        assert!(b_better.is_empty());
+    }
+}
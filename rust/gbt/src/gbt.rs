@@ -1,11 +1,21 @@
 use priority_queue::PriorityQueue;
-use std::{cmp::Ordering, collections::HashSet, mem::ManuallyDrop};
+use std::{cmp::Ordering, collections::HashSet, mem::ManuallyDrop, sync::LazyLock};
 use tracing::{info, trace};
 
 use crate::{
     audit_transaction::{partial_cmp_uid_score, AuditTransaction},
-    u32_hasher_types::{u32hashset_new, u32priority_queue_with_capacity, U32HasherState},
-    GbtResult, ThreadTransactionsMap, thread_acceleration::ThreadAcceleration,
+    block_template::subsidy,
+    explain::{ExplainOutcome, Tracer},
+    feerate_diagram::FeerateDiagram,
+    histogram::FeeHistogram,
+    mempool::Mempool,
+    template_comparison::diff_templates,
+    thread_acceleration::FeeDeltas,
+    timelock::ChainTip,
+    u32_hasher_types::{
+        u32hashmap_with_capacity, u32hashset_new, u32priority_queue_with_capacity, U32HasherState,
+    },
+    BlockReward, BlockScores, Chunk, GbtOptions, GbtResult, Relatives, ThreadTransactionsMap, Unmineable, UnmineableReason,
 };
 
 const BLOCK_SIGOPS: u32 = 80_000;
@@ -15,6 +25,163 @@ const BLOCK_RESERVED_SIGOPS: u32 = 400;
 type AuditPool = Vec<Option<ManuallyDrop<AuditTransaction>>>;
 type ModifiedQueue = PriorityQueue<u32, TxPriority, U32HasherState>;
 
+static NO_UIDS: LazyLock<HashSet<u32, U32HasherState>> = LazyLock::new(u32hashset_new);
+
+/// Mempool state applied on top of the transactions themselves in a gbt run.
+pub struct GbtContext<'a> {
+    /// Fee deltas by uid, deltas for uids outside of the mempool are ignored
+    pub fee_deltas: &'a FeeDeltas,
+    /// Transactions filtered out of every block and reported in the overflow instead.
+    /// Their in-mempool descendants must be excluded too, or they would be treated as minable.
+    pub excluded: &'a HashSet<u32, U32HasherState>,
+    /// Transactions with a missing ancestor, which are left out just like `excluded`
+    pub orphans: &'a HashSet<u32, U32HasherState>,
+    /// Transactions whose rates are reported even if they did not change
+    pub dirty: &'a HashSet<u32, U32HasherState>,
+    /// If set, transactions are held back from projected blocks until their timelocks expire
+    pub chain_tip: Option<ChainTip>,
+    /// If set, packages below this feerate are left out of every block, like `blockmintxfee`
+    pub min_fee_rate: Option<f64>,
+}
+
+impl<'a> GbtContext<'a> {
+    /// A context with fee deltas only, for running gbt over a detached set of transactions
+    pub fn new(fee_deltas: &'a FeeDeltas) -> Self {
+        Self {
+            fee_deltas,
+            excluded: &NO_UIDS,
+            orphans: &NO_UIDS,
+            dirty: &NO_UIDS,
+            chain_tip: None,
+            min_fee_rate: None,
+        }
+    }
+}
+
+/// Run gbt over a copy of the whole mempool with its orphans, chain tip and minimum feerate,
+/// leaving the stored rates untouched.
+///
+/// Unlike `make` and `update`, the `policy` option only filters the blocks, without
+/// projecting the unfiltered blocks alongside them.
+pub fn project(
+    mempool: &Mempool,
+    fee_deltas: &FeeDeltas,
+    max_block_weight: u32,
+    max_blocks: usize,
+    options: &GbtOptions,
+) -> GbtResult {
+    project_transactions(
+        mempool,
+        mempool.transactions.clone(),
+        fee_deltas,
+        max_block_weight,
+        max_blocks,
+        options,
+    )
+}
+
+/// Like `project`, but over a modified copy of the mempool's transactions,
+/// such as one with transactions added or removed.
+///
+/// The `policy` option is still evaluated against `mempool`.
+pub fn project_transactions(
+    mempool: &Mempool,
+    mut transactions: ThreadTransactionsMap,
+    fee_deltas: &FeeDeltas,
+    max_block_weight: u32,
+    max_blocks: usize,
+    options: &GbtOptions,
+) -> GbtResult {
+    let excluded = options
+        .policy
+        .as_ref()
+        .map_or_else(u32hashset_new, |policy| policy.excluded(mempool));
+    let max_uid = transactions.keys().copied().max().unwrap_or(0) as usize;
+    gbt(
+        &mut transactions,
+        &GbtContext {
+            excluded: &excluded,
+            orphans: &mempool.orphans,
+            chain_tip: mempool.chain_tip,
+            min_fee_rate: mempool.min_fee_rate,
+            ..GbtContext::new(fee_deltas)
+        },
+        max_uid,
+        max_block_weight,
+        max_blocks,
+        options,
+    )
+}
+
+/// Run gbt over the mempool itself, updating its stored rates, as `make` and `update` do.
+///
+/// Transactions whose fee delta changed since the last run are reported as dirty. With the
+/// `policy` option, the unfiltered blocks are projected alongside, and the result includes
+/// how the first block differs from the unfiltered one.
+pub fn run(
+    mempool: &mut Mempool,
+    max_uid: usize,
+    max_block_weight: u32,
+    max_blocks: usize,
+    options: &GbtOptions,
+) -> GbtResult {
+    let dirty = mempool.take_dirty();
+    let excluded = options
+        .policy
+        .as_ref()
+        .map(|policy| policy.excluded(mempool));
+    let context = GbtContext {
+        excluded: excluded.as_ref().unwrap_or(&NO_UIDS),
+        fee_deltas: &mempool.fee_deltas,
+        orphans: &mempool.orphans,
+        dirty: &dirty,
+        chain_tip: mempool.chain_tip,
+        min_fee_rate: mempool.min_fee_rate,
+    };
+
+    // project the unfiltered blocks on the side, to show what the policy changes
+    let unfiltered_block = excluded.as_ref().map(|_| {
+        let mut unfiltered = mempool.transactions.clone();
+        let result = gbt(
+            &mut unfiltered,
+            &GbtContext {
+                excluded: &NO_UIDS,
+                dirty: &NO_UIDS,
+                ..context
+            },
+            max_uid,
+            max_block_weight,
+            max_blocks,
+            &GbtOptions {
+                policy: None,
+                ..options.selection_only()
+            },
+        );
+        result.blocks.into_iter().next().unwrap_or_default()
+    });
+
+    let result = gbt(
+        &mut mempool.transactions,
+        &context,
+        max_uid,
+        max_block_weight,
+        max_blocks,
+        options,
+    );
+    let policy_diff = unfiltered_block.map(|unfiltered_block| {
+        diff_templates(
+            &mempool.transactions,
+            &mempool.fee_deltas,
+            &unfiltered_block,
+            result.blocks.first().map_or(&[], Vec::as_slice),
+        )
+    });
+    GbtResult {
+        policy_diff,
+        ..result
+    }
+}
+
 #[derive(Debug)]
 struct TxPriority {
     uid: u32,
@@ -29,15 +196,16 @@ impl PartialEq for TxPriority {
 impl Eq for TxPriority {}
 impl PartialOrd for TxPriority {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        partial_cmp_uid_score(
-            (self.uid, self.order, self.score),
-            (other.uid, other.order, other.score),
-        )
+        Some(self.cmp(other))
     }
 }
 impl Ord for TxPriority {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).expect("score will never be NaN")
+        partial_cmp_uid_score(
+            (self.uid, self.order, self.score),
+            (other.uid, other.order, other.score),
+        )
+        .expect("score will never be NaN")
     }
 }
 
@@ -53,17 +221,12 @@ impl Ord for TxPriority {
 #[allow(clippy::cognitive_complexity)]
 pub fn gbt(
     mempool: &mut ThreadTransactionsMap,
-    accelerations: &[ThreadAcceleration],
+    context: &GbtContext,
     max_uid: usize,
     max_block_weight: u32,
     max_blocks: usize,
+    options: &GbtOptions,
 ) -> GbtResult {
-    let mut indexed_accelerations = Vec::with_capacity(max_uid + 1);
-    indexed_accelerations.resize(max_uid + 1, None);
-    for acceleration in accelerations {
-        indexed_accelerations[acceleration.uid as usize] = Some(acceleration);
-    }
-
     info!("Initializing working vecs with uid capacity for {}", max_uid + 1);
     let mempool_len = mempool.len();
     let mut audit_pool: AuditPool = Vec::with_capacity(max_uid + 1);
@@ -71,11 +234,40 @@ pub fn gbt(
     let mut mempool_stack: Vec<u32> = Vec::with_capacity(mempool_len);
     let mut clusters: Vec<Vec<u32>> = Vec::new();
     let mut block_weights: Vec<u32> = Vec::new();
+    let mut block_scores: Vec<BlockScores> = Vec::new();
+    let record_chunks = options.chunks.unwrap_or(false);
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let record_relatives = options.relatives.unwrap_or(false);
+    let mut relatives: Vec<Relatives> = Vec::new();
+    let mut histogram = options.histogram_buckets.as_deref().map(FeeHistogram::new);
+    let mut diagram = options
+        .diagram
+        .unwrap_or(false)
+        .then(FeerateDiagram::default);
+    // transactions first seen after this have not reached miners yet
+    let propagation_cutoff = options
+        .now
+        .zip(options.propagation_delay)
+        .map(|(now, delay)| now - delay);
+    let mut tracer = options.explain.map(Tracer::new);
 
     info!("Initializing working structs");
     for (uid, tx) in &mut *mempool {
-        let acceleration = indexed_accelerations.get(*uid as usize);
-        let audit_tx = AuditTransaction::from_thread_transaction(tx, acceleration.copied());
+        if context.excluded.contains(uid) || context.orphans.contains(uid) {
+            continue;
+        }
+        let fee_delta = context.fee_deltas.get(uid).copied().unwrap_or(0.0);
+        let mut audit_tx =
+            AuditTransaction::from_thread_transaction(tx, fee_delta, context.dirty.contains(uid));
+        if let Some(chain_tip) = context.chain_tip {
+            audit_tx.timelock_block = chain_tip.min_block(tx);
+            audit_tx.min_block = audit_tx.timelock_block;
+        }
+        if let Some(cutoff) = propagation_cutoff {
+            if tx.first_seen.is_some_and(|first_seen| first_seen > cutoff) {
+                audit_tx.min_block = audit_tx.min_block.max(1);
+            }
+        }
         // Safety: audit_pool and mempool_stack must always contain the same transactions
         audit_pool[*uid as usize] = Some(ManuallyDrop::new(audit_tx));
         mempool_stack.push(*uid);
@@ -85,6 +277,9 @@ pub fn gbt(
     for txid in &mempool_stack {
         set_relatives(*txid, &mut audit_pool);
     }
+    if context.chain_tip.is_some() || propagation_cutoff.is_some() {
+        set_package_min_blocks(&mempool_stack, &mut audit_pool);
+    }
     trace!("Post relative graph Audit Pool: {:#?}", audit_pool);
 
     info!("Sorting by descending ancestor score");
@@ -106,11 +301,18 @@ pub fn gbt(
     let mut blocks: Vec<Vec<u32>> = Vec::new();
     let mut block_weight: u32 = BLOCK_RESERVED_WEIGHT;
     let mut block_sigops: u32 = BLOCK_RESERVED_SIGOPS;
+    let mut scores = BlockScores::default();
     // No need to be bigger than 4096 transactions for the per-block transaction Vec.
     let initial_txes_per_block: usize = 4096.min(mempool_len);
     let mut transactions: Vec<u32> = Vec::with_capacity(initial_txes_per_block);
     let mut modified: ModifiedQueue = u32priority_queue_with_capacity(mempool_len);
     let mut overflow: Vec<u32> = Vec::new();
+    // why each package was last put in the overflow
+    let mut overflow_reasons = u32hashmap_with_capacity(0);
+    // the package feerate of every transaction rejected by the minimum feerate
+    let mut below_min_fee = u32hashmap_with_capacity(0);
+    // whether any overflow packages are only waiting for their timelocks
+    let mut deferred = false;
     let mut failures = 0;
     while !mempool_stack.is_empty() || !modified.is_empty() {
         // This trace log storm is big, so to make scrolling through
@@ -150,14 +352,55 @@ pub fn gbt(
                 modified.pop();
             }
 
-            if blocks.len() < (max_blocks - 1)
+            if context
+                .min_fee_rate
+                .is_some_and(|min_fee_rate| next_tx.cluster_rate() < min_fee_rate)
+            {
+                // everything left scores lower, unless it is modified by a later package
+                below_min_fee.insert(next_tx.uid, next_tx.cluster_rate());
+                if let Some(tracer) = &mut tracer {
+                    tracer.skipped(next_tx, blocks.len(), ExplainOutcome::BelowMinFee);
+                }
+            } else if next_tx.min_block > blocks.len() {
+                // hold this package back until its timelocks allow it,
+                // or for good if they will not within the projected blocks
+                overflow.push(next_tx.uid);
+                overflow_reasons.insert(
+                    next_tx.uid,
+                    if next_tx.timelock_block > blocks.len() {
+                        UnmineableReason::Timelocked
+                    } else {
+                        UnmineableReason::Unpropagated
+                    },
+                );
+                deferred |= next_tx.min_block < max_blocks;
+                if let Some(tracer) = &mut tracer {
+                    tracer.skipped(next_tx, blocks.len(), ExplainOutcome::Deferred);
+                }
+            } else if blocks.len() < (max_blocks - 1)
                 && ((block_weight + (4 * next_tx.ancestor_sigop_adjusted_vsize())
                     >= max_block_weight - 4_000)
                     || (block_sigops + next_tx.ancestor_sigops() > BLOCK_SIGOPS))
             {
                 // hold this package in an overflow list while we check for smaller options
                 overflow.push(next_tx.uid);
+                overflow_reasons.insert(
+                    next_tx.uid,
+                    if block_sigops + next_tx.ancestor_sigops() > BLOCK_SIGOPS {
+                        UnmineableReason::Sigops
+                    } else {
+                        UnmineableReason::Weight
+                    },
+                );
                 failures += 1;
+                scores.max_rejected = Some(
+                    scores
+                        .max_rejected
+                        .map_or(next_tx.score(), |max| max.max(next_tx.score())),
+                );
+                if let Some(tracer) = &mut tracer {
+                    tracer.skipped(next_tx, blocks.len(), ExplainOutcome::Overflow);
+                }
             } else {
                 let mut package: Vec<(u32, u32, usize)> = Vec::new();
                 let mut cluster: Vec<u32> = Vec::new();
@@ -181,7 +424,19 @@ pub fn gbt(
                 });
                 package.push((next_tx.uid, next_tx.order(), next_tx.ancestors.len()));
 
+                if record_relatives && is_cluster {
+                    relatives.extend(package_relatives(&package, next_tx.uid, &audit_pool));
+                }
+
                 let cluster_rate = next_tx.cluster_rate();
+                let (root_txid, root_score) = (next_tx.uid, next_tx.score());
+                scores.min_accepted = Some(
+                    scores
+                        .min_accepted
+                        .map_or(root_score, |min| min.min(root_score)),
+                );
+                let mut package_fee: u64 = 0;
+                let mut package_sigop_adjusted_weight: u32 = 0;
 
                 for (txid, _, _) in &package {
                     cluster.push(*txid);
@@ -191,10 +446,31 @@ pub fn gbt(
                         transactions.push(tx.uid);
                         block_weight += tx.weight;
                         block_sigops += tx.sigops;
+                        package_fee += tx.fee;
+                        package_sigop_adjusted_weight += tx.sigop_adjusted_weight;
                     }
                     update_descendants(*txid, &mut audit_pool, &mut modified, cluster_rate);
                 }
 
+                let package_vsize = f64::from(package_sigop_adjusted_weight) / 4.0;
+                if let Some(diagram) = &mut diagram {
+                    diagram.push_chunk(package_fee as f64, package_vsize);
+                }
+                if record_chunks || tracer.is_some() {
+                    let chunk = Chunk {
+                        uids: cluster.clone(),
+                        fee: package_fee as f64,
+                        vsize: package_vsize,
+                        effective_fee_per_vsize: cluster_rate,
+                    };
+                    if let Some(tracer) = &mut tracer {
+                        tracer.selected(chunk.clone(), root_txid, root_score, blocks.len());
+                    }
+                    if record_chunks {
+                        chunks.push(chunk);
+                    }
+                }
+
                 if is_cluster {
                     clusters.push(cluster);
                 }
@@ -209,19 +485,25 @@ pub fn gbt(
         let queue_is_empty = mempool_stack.is_empty() && modified.is_empty();
         if (exceeded_package_tries || queue_is_empty) && blocks.len() < (max_blocks - 1) {
             // finalize this block
-            if transactions.is_empty() {
+            if transactions.is_empty() && !deferred {
                 info!("trying to push an empty block! breaking loop! mempool {:#?} | modified {:#?} | overflow {:#?}", mempool_stack.len(), modified.len(), overflow.len());
                 break;
             }
 
             blocks.push(transactions);
             block_weights.push(block_weight);
+            if let Some(tracer) = &mut tracer {
+                tracer.block_finished(scores.min_accepted);
+            }
+            block_scores.push(scores);
 
             // reset for the next block
             transactions = Vec::with_capacity(initial_txes_per_block);
             block_weight = BLOCK_RESERVED_WEIGHT;
             block_sigops = BLOCK_RESERVED_SIGOPS;
+            scores = BlockScores::default();
             failures = 0;
+            deferred = false;
             // 'overflow' packages didn't fit in this block, but are valid candidates for the next
             overflow.reverse();
             for overflowed in &overflow {
@@ -247,7 +529,99 @@ pub fn gbt(
     if !transactions.is_empty() {
         blocks.push(transactions);
         block_weights.push(block_weight);
+        if let Some(tracer) = &mut tracer {
+            tracer.block_finished(scores.min_accepted);
+        }
+        block_scores.push(scores);
     }
+    let mut excluded_overflow: Vec<u32> = context
+        .excluded
+        .iter()
+        .chain(context.orphans.iter())
+        .copied()
+        .filter(|uid| mempool.contains_key(uid))
+        .collect();
+    excluded_overflow.sort_unstable();
+    excluded_overflow.dedup();
+    overflow.extend(excluded_overflow);
+
+    let below_min_fee = context.min_fee_rate.map(|_| {
+        let mut below_min_fee: Vec<(u32, f64)> = below_min_fee
+            .into_iter()
+            .filter(|(uid, _)| {
+                audit_pool
+                    .get(*uid as usize)
+                    .and_then(Option::as_ref)
+                    .is_some_and(|tx| !tx.used)
+            })
+            .collect();
+        below_min_fee.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        below_min_fee
+    });
+
+    // packages which did not fit are only left over if they would not fit in an empty block
+    let unmineable: Vec<Unmineable> = overflow
+        .iter()
+        .map(|uid| Unmineable {
+            uid: *uid,
+            reason: if context.orphans.contains(uid) {
+                UnmineableReason::Orphaned
+            } else if context.excluded.contains(uid) {
+                UnmineableReason::Filtered
+            } else {
+                overflow_reasons
+                    .get(uid)
+                    .copied()
+                    .unwrap_or(UnmineableReason::Weight)
+            },
+        })
+        .chain(below_min_fee.iter().flatten().map(|(uid, _)| Unmineable {
+            uid: *uid,
+            reason: UnmineableReason::BelowMinFee,
+        }))
+        .collect();
+    let below_min_fee = below_min_fee.map(|below_min_fee| {
+        below_min_fee
+            .into_iter()
+            .map(|(uid, rate)| vec![f64::from(uid), rate])
+            .collect()
+    });
+
+    let tip_height = options
+        .tip_height
+        .or_else(|| context.chain_tip.map(|chain_tip| chain_tip.height));
+    let block_rewards = tip_height.map(|tip_height| {
+        let mut cumulative_revenue: u64 = 0;
+        blocks
+            .iter()
+            .zip(tip_height + 1..)
+            .map(|(block, height)| {
+                let subsidy = subsidy(height);
+                let fees: u64 = block
+                    .iter()
+                    .filter_map(|uid| mempool.get(uid))
+                    .map(|tx| tx.fee as u64)
+                    .sum();
+                let fee_deltas: f64 = block
+                    .iter()
+                    .filter_map(|uid| context.fee_deltas.get(uid))
+                    .sum();
+                cumulative_revenue += subsidy + fees;
+                BlockReward {
+                    height,
+                    subsidy: subsidy as f64,
+                    fees: fees as f64,
+                    fee_deltas,
+                    fee_share: if fees > 0 {
+                        fees as f64 / (subsidy + fees) as f64
+                    } else {
+                        0.0
+                    },
+                    cumulative_revenue: cumulative_revenue as f64,
+                }
+            })
+            .collect()
+    });
 
     info!("make a list of dirty transactions and their new rates");
     let mut rates: Vec<Vec<f64>> = Vec::new();
@@ -259,6 +633,13 @@ pub fn gbt(
                 rates.push(vec![f64::from(*uid), audit_tx.effective_fee_per_vsize]);
                 thread_tx.effective_fee_per_vsize = audit_tx.effective_fee_per_vsize;
             }
+            if let Some(histogram) = &mut histogram {
+                histogram.add(
+                    audit_tx.effective_fee_per_vsize,
+                    thread_tx.weight,
+                    thread_tx.fee,
+                );
+            }
             // Drops the AuditTransaction manually
             // There are no audit_txs that are not in the mempool HashMap
             // So there is guaranteed to be no memory leaks.
@@ -273,12 +654,56 @@ pub fn gbt(
     GbtResult {
         blocks,
         block_weights,
+        block_scores,
         clusters,
+        cluster_ids: None,
+        tx_clusters: None,
         rates,
         overflow,
+        chunks: record_chunks.then_some(chunks),
+        relatives: record_relatives.then_some(relatives),
+        histogram: histogram.map(FeeHistogram::into_buckets),
+        diagram: diagram.map(FeerateDiagram::into_points),
+        conflicts: None,
+        cascaded: None,
+        policy_diff: None,
+        below_min_fee,
+        unmineable,
+        explanation: tracer.map(Tracer::finish),
+        block_rewards,
     }
 }
 
+// find the relatives of each package member within the package,
+// before any of them are marked as used
+fn package_relatives(
+    package: &[(u32, u32, usize)],
+    root_txid: u32,
+    audit_pool: &AuditPool,
+) -> Vec<Relatives> {
+    let members: Vec<&AuditTransaction> = package
+        .iter()
+        .filter_map(|(txid, _, _)| audit_pool.get(*txid as usize).and_then(Option::as_deref))
+        .collect();
+    members
+        .iter()
+        .map(|member| Relatives {
+            uid: member.uid,
+            ancestors: members
+                .iter()
+                .filter(|other| member.ancestors.contains(&other.uid))
+                .map(|other| other.uid)
+                .collect(),
+            descendants: members
+                .iter()
+                .filter(|other| other.ancestors.contains(&member.uid))
+                .map(|other| other.uid)
+                .collect(),
+            best_descendant: (member.uid != root_txid).then_some(root_txid),
+        })
+        .collect()
+}
+
 fn next_valid_from_stack<'a>(
     mempool_stack: &mut Vec<u32>,
     audit_pool: &'a AuditPool,
@@ -313,6 +738,36 @@ fn next_valid_from_queue<'a>(
     None
 }
 
+// a package can not be mined before any of its ancestors
+fn set_package_min_blocks(txids: &[u32], audit_pool: &mut AuditPool) {
+    let package_min_blocks: Vec<(u32, usize, usize)> = txids
+        .iter()
+        .filter_map(|txid| audit_pool.get(*txid as usize).and_then(Option::as_ref))
+        .map(|tx| {
+            let (min_block, timelock_block) = tx
+                .ancestors
+                .iter()
+                .filter_map(|ancestor| audit_pool.get(*ancestor as usize).and_then(Option::as_ref))
+                .fold(
+                    (tx.min_block, tx.timelock_block),
+                    |(min, timelock), ancestor| {
+                        (
+                            min.max(ancestor.min_block),
+                            timelock.max(ancestor.timelock_block),
+                        )
+                    },
+                );
+            (tx.uid, min_block, timelock_block)
+        })
+        .collect();
+    for (txid, min_block, timelock_block) in package_min_blocks {
+        if let Some(Some(tx)) = audit_pool.get_mut(txid as usize) {
+            tx.min_block = min_block;
+            tx.timelock_block = timelock_block;
+        }
+    }
+}
+
 fn set_relatives(txid: u32, audit_pool: &mut AuditPool) {
     let mut parents: HashSet<u32, U32HasherState> = u32hashset_new();
     if let Some(Some(tx)) = audit_pool.get(txid as usize) {
@@ -383,9 +838,8 @@ fn update_descendants(
     let root_sigops: u32;
     if let Some(Some(root_tx)) = audit_pool.get(root_txid as usize) {
         for descendant_id in &root_tx.children {
-            if !visited.contains(descendant_id) {
+            if visited.insert(*descendant_id) {
                 descendant_stack.push(*descendant_id);
-                visited.insert(*descendant_id);
             }
         }
         root_fee = root_tx.fee;
@@ -431,11 +885,407 @@ fn update_descendants(
 
             // add this node's children to the stack
             for child_id in &descendant.children {
-                if !visited.contains(child_id) {
+                if visited.insert(*child_id) {
                     descendant_stack.push(*child_id);
-                    visited.insert(*child_id);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{gbt, run, GbtContext};
+    use crate::{
+        mempool::Mempool,
+        policy::PolicyFilter,
+        thread_transaction::{test_tx, ThreadTransaction},
+        timelock::ChainTip,
+        u32_hasher_types::u32hashmap_with_capacity,
+        GbtOptions, UnmineableReason,
+    };
+
+    #[test]
+    fn test_propagation_delay() {
+        let mut mempool = u32hashmap_with_capacity(3);
+        for (uid, first_seen, inputs) in [
+            (0, Some(1000.0), vec![]),
+            (1, Some(1095.0), vec![]),
+            (2, None, vec![1]),
+        ] {
+            mempool.insert(
+                uid,
+                ThreadTransaction {
+                    first_seen,
+                    ..test_tx(uid, 1000.0, 400, inputs)
+                },
+            );
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+        let context = GbtContext::new(&no_deltas);
+
+        let result = gbt(
+            &mut mempool,
+            &context,
+            2,
+            4_000_000,
+            8,
+            &GbtOptions::default(),
+        );
+        assert_eq!(result.blocks.len(), 1);
+
+        // tx 1 is too fresh for the next block, and holds back its child
+        let options = GbtOptions {
+            now: Some(1100.0),
+            propagation_delay: Some(10.0),
+            ..GbtOptions::default()
+        };
+        let result = gbt(&mut mempool, &context, 2, 4_000_000, 8, &options);
+        assert_eq!(result.blocks[0], vec![0]);
+        assert_eq!(result.blocks[1].len(), 2);
+
+        // with a single block, they are left out for propagation rather than timelocks
+        let result = gbt(&mut mempool, &context, 2, 4_000_000, 1, &options);
+        assert_eq!(result.blocks, vec![vec![0]]);
+        let mut reasons: Vec<(u32, UnmineableReason)> = result
+            .unmineable
+            .into_iter()
+            .map(|unmineable| (unmineable.uid, unmineable.reason))
+            .collect();
+        reasons.sort_unstable_by_key(|(uid, _)| *uid);
+        assert_eq!(
+            reasons,
+            vec![
+                (1, UnmineableReason::Unpropagated),
+                (2, UnmineableReason::Unpropagated)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut mempool = Mempool::with_capacity(4);
+        for (uid, fee, flags, inputs) in [
+            (0, 5000.0, 1.0, vec![]),
+            (1, 1000.0, 0.0, vec![0]),
+            (2, 1000.0, 0.0, vec![]),
+            (3, 500.0, 2.0, vec![]),
+        ] {
+            mempool.insert(ThreadTransaction {
+                flags: Some(flags),
+                ..test_tx(uid, fee, 400, inputs)
+            });
+        }
+        let options = GbtOptions {
+            policy: Some(PolicyFilter {
+                exclude_flags: Some(3.0),
+                include_flags: Some(2.0),
+            }),
+            ..GbtOptions::default()
+        };
+
+        // tx 0 and its child are filtered out, tx 3 is let back in
+        let result = run(&mut mempool, 3, 4_000_000, 8, &options);
+        assert_eq!(result.blocks, vec![vec![2, 3]]);
+        assert_eq!(result.overflow, vec![0, 1]);
+        let reasons: Vec<(u32, UnmineableReason)> = result
+            .unmineable
+            .into_iter()
+            .map(|unmineable| (unmineable.uid, unmineable.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (0, UnmineableReason::Filtered),
+                (1, UnmineableReason::Filtered)
+            ]
+        );
+        let policy_diff = result.policy_diff.expect("a policy was given");
+        assert_eq!(policy_diff.missing, vec![0, 1]);
+        assert!(policy_diff.extra.is_empty());
+        assert!(policy_diff.reordered.is_empty());
+        assert_eq!(policy_diff.expected_fees, 7500.0);
+        assert_eq!(policy_diff.actual_fees, 1500.0);
+
+        let result = run(&mut mempool, 3, 4_000_000, 8, &GbtOptions::default());
+        assert_eq!(result.blocks[0].len(), 4);
+        assert!(result.overflow.is_empty());
+        assert!(result.policy_diff.is_none());
+    }
+
+    #[test]
+    fn test_min_fee_rate() {
+        let mut mempool = u32hashmap_with_capacity(4);
+        for (uid, fee, inputs) in [
+            (0, 1000.0, vec![]),
+            (1, 50.0, vec![]),
+            (2, 1000.0, vec![1]),
+            (3, 0.0, vec![]),
+            (4, 20.0, vec![]),
+        ] {
+            mempool.insert(uid, test_tx(uid, fee, 400, inputs));
+        }
+        // too big for any block, but worth mining
+        mempool.insert(5, test_tx(5, 10_000_000.0, 4_000_000, vec![]));
+        let no_deltas = u32hashmap_with_capacity(0);
+        let context = GbtContext {
+            min_fee_rate: Some(1.0),
+            ..GbtContext::new(&no_deltas)
+        };
+
+        // tx 1 is paid for by its child, but 3 and 4 are never worth mining
+        let result = gbt(
+            &mut mempool,
+            &context,
+            5,
+            4_000_000,
+            8,
+            &GbtOptions::default(),
+        );
+        assert_eq!(result.blocks.len(), 1);
+        assert_eq!(result.blocks[0].len(), 3);
+        assert_eq!(result.overflow, vec![5]);
+        assert_eq!(
+            result.below_min_fee,
+            Some(vec![vec![4.0, 0.2], vec![3.0, 0.0]])
+        );
+
+        // every transaction is in exactly one of the blocks, the overflow or below_min_fee
+        let mut uids: Vec<u32> = result
+            .blocks
+            .iter()
+            .flatten()
+            .chain(result.overflow.iter())
+            .copied()
+            .chain(
+                result
+                    .below_min_fee
+                    .iter()
+                    .flatten()
+                    .map(|below| below[0] as u32),
+            )
+            .collect();
+        uids.sort_unstable();
+        assert_eq!(uids, vec![0, 1, 2, 3, 4, 5]);
+        let reasons: Vec<(u32, UnmineableReason)> = result
+            .unmineable
+            .into_iter()
+            .map(|unmineable| (unmineable.uid, unmineable.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (5, UnmineableReason::Weight),
+                (4, UnmineableReason::BelowMinFee),
+                (3, UnmineableReason::BelowMinFee)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relatives() {
+        let mut mempool = u32hashmap_with_capacity(4);
+        // tx 0 is selected on its own first, then tx 3 pays for the rest of its ancestors
+        for (uid, fee, inputs) in [
+            (0, 10_000.0, vec![]),
+            (1, 100.0, vec![]),
+            (2, 3900.0, vec![0, 1]),
+            (3, 2600.0, vec![2]),
+        ] {
+            mempool.insert(uid, test_tx(uid, fee, 400, inputs));
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&no_deltas),
+            3,
+            4_000_000,
+            8,
+            &GbtOptions {
+                relatives: Some(true),
+                ..GbtOptions::default()
+            },
+        );
+        assert_eq!(result.blocks, vec![vec![0, 1, 2, 3]]);
+
+        // the already selected tx 0 is left out of the package's relatives
+        let relatives = result.relatives.expect("relatives requested");
+        let uids: Vec<u32> = relatives.iter().map(|relatives| relatives.uid).collect();
+        assert_eq!(uids, vec![1, 2, 3]);
+        for (relatives, (ancestors, descendants, best_descendant)) in relatives.iter().zip([
+            (vec![], vec![2, 3], Some(3)),
+            (vec![1], vec![3], Some(3)),
+            (vec![1, 2], vec![], None),
+        ]) {
+            assert_eq!(relatives.ancestors, ancestors);
+            assert_eq!(relatives.descendants, descendants);
+            assert_eq!(relatives.best_descendant, best_descendant);
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut mempool = u32hashmap_with_capacity(3);
+        // a 1 sat/vB parent paid for by a 19 sat/vB child, and a 5 sat/vB tx
+        for (uid, fee, inputs) in [(0, 100.0, vec![]), (1, 1900.0, vec![0]), (2, 500.0, vec![])] {
+            mempool.insert(uid, test_tx(uid, fee, 400, inputs));
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&no_deltas),
+            2,
+            4_000_000,
+            8,
+            &GbtOptions {
+                histogram_buckets: Some(vec![1.0, 5.0, 10.0]),
+                ..GbtOptions::default()
+            },
+        );
+
+        // both are bucketed by the 10 sat/vB package rate
+        let buckets: Vec<(f64, u32, f64, f64)> = result
+            .histogram
+            .expect("histogram requested")
+            .into_iter()
+            .map(|bucket| (bucket.fee_rate, bucket.count, bucket.vsize, bucket.fee))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (1.0, 0, 0.0, 0.0),
+                (5.0, 1, 100.0, 500.0),
+                (10.0, 2, 200.0, 2000.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_oversized_package() {
+        let mut mempool = u32hashmap_with_capacity(2);
+        mempool.insert(
+            0,
+            ThreadTransaction {
+                weight: 4_000_000,
+                ..test_tx(0, 100_000.0, 400, vec![])
+            },
+        );
+        mempool.insert(1, test_tx(1, 1000.0, 400, vec![]));
+        let no_deltas = u32hashmap_with_capacity(0);
+
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&no_deltas),
+            1,
+            4_000_000,
+            8,
+            &GbtOptions::default(),
+        );
+        assert_eq!(result.blocks, vec![vec![1]]);
+        assert_eq!(result.overflow, vec![0]);
+        assert_eq!(result.unmineable[0].reason, UnmineableReason::Weight);
+    }
+
+    #[test]
+    fn test_block_scores() {
+        let mut mempool = u32hashmap_with_capacity(3);
+        for (uid, fee) in [(0, 3000.0), (1, 2000.0), (2, 1000.0)] {
+            mempool.insert(uid, test_tx(uid, fee, 400, vec![]));
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+
+        // room for two transactions per block
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&no_deltas),
+            2,
+            8_900,
+            2,
+            &GbtOptions::default(),
+        );
+        assert_eq!(result.blocks, vec![vec![0, 1], vec![2]]);
+        assert_eq!(result.block_scores[0].min_accepted, Some(20.0));
+        assert_eq!(result.block_scores[0].max_rejected, Some(10.0));
+        assert_eq!(result.block_scores[1].min_accepted, Some(10.0));
+        assert_eq!(result.block_scores[1].max_rejected, None);
+    }
+
+    #[test]
+    fn test_block_rewards() {
+        let mut mempool = u32hashmap_with_capacity(3);
+        for (uid, fee) in [(0, 3000.0), (1, 2000.0), (2, 1000.0)] {
+            mempool.insert(uid, test_tx(uid, fee, 400, vec![]));
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&no_deltas),
+            2,
+            8_900,
+            2,
+            &GbtOptions::default(),
+        );
+        assert!(result.block_rewards.is_none());
+
+        // the second block is the first of a new halving epoch
+        let context = GbtContext {
+            chain_tip: Some(ChainTip {
+                height: 839_998,
+                median_time_past: 0,
+            }),
+            ..GbtContext::new(&no_deltas)
+        };
+        let result = gbt(&mut mempool, &context, 2, 8_900, 2, &GbtOptions::default());
+        let rewards = result.block_rewards.expect("chain tip is set");
+        assert_eq!(rewards[0].height, 839_999);
+        assert_eq!(rewards[0].subsidy, 625_000_000.0);
+        assert_eq!(rewards[0].fees, 5000.0);
+        assert_eq!(rewards[0].fee_share, 5000.0 / 625_005_000.0);
+        assert_eq!(rewards[1].subsidy, 312_500_000.0);
+        assert_eq!(rewards[1].fees, 1000.0);
+        assert_eq!(rewards[1].cumulative_revenue, 937_506_000.0);
+
+        // or from the tip height alone, without enforcing timelocks
+        let options = GbtOptions {
+            tip_height: Some(839_998),
+            ..GbtOptions::default()
+        };
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&no_deltas),
+            2,
+            8_900,
+            2,
+            &options,
+        );
+        let rewards = result.block_rewards.expect("tip height is set");
+        assert_eq!(rewards[1].height, 840_000);
+        assert_eq!(rewards[1].cumulative_revenue, 937_506_000.0);
+
+        // accelerations are reported apart from the fees, which the diagram includes them in
+        let mut deltas = u32hashmap_with_capacity(1);
+        deltas.insert(2, 500.0);
+        let options = GbtOptions {
+            diagram: Some(true),
+            ..options
+        };
+        let result = gbt(
+            &mut mempool,
+            &GbtContext::new(&deltas),
+            2,
+            8_900,
+            2,
+            &options,
+        );
+        let rewards = result.block_rewards.expect("tip height is set");
+        assert_eq!(rewards[1].fees, 1000.0);
+        assert_eq!(rewards[1].fee_deltas, 500.0);
+        assert_eq!(rewards[1].cumulative_revenue, 937_506_000.0);
+        let total_fees: f64 = rewards
+            .iter()
+            .map(|reward| reward.fees + reward.fee_deltas)
+            .sum();
+        let diagram = result.diagram.expect("diagram was requested");
+        assert_eq!(diagram.last().map(|point| point.fee), Some(total_fees));
+    }
+}
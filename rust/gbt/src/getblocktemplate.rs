@@ -0,0 +1,156 @@
+use napi_derive::napi;
+
+use crate::{
+    block_template::{subsidy, TemplateTransaction},
+    mempool::Mempool,
+    u32_hasher_types::u32hashmap_with_capacity,
+};
+
+/// Core's consensus limit on the sigop cost of a block
+const MAX_BLOCK_SIGOPS_COST: u32 = 80_000;
+/// Core's consensus limit on the serialized size of a block, in bytes
+const MAX_BLOCK_SERIALIZED_SIZE: u32 = 4_000_000;
+
+/// A transaction entry of a BIP22 `getblocktemplate` result.
+///
+/// `depends` are the 1-based indices of its in-block parents, `fee` excludes any accelerations,
+/// and `sigops` is the sigop cost. The data and hashes are only known for transactions given
+/// with their full data, or with a `txid` for the txid alone.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct GetBlockTemplateTransaction {
+    pub data: Option<String>,
+    pub txid: Option<String>,
+    pub hash: Option<String>,
+    pub depends: Vec<u32>,
+    pub fee: f64,
+    pub sigops: u32,
+    pub weight: u32,
+}
+
+/// A BIP22/BIP23 `getblocktemplate` result, keeping the node's field names.
+///
+/// Only the fields which follow from the mempool are included, so the header fields
+/// (`version`, `previousblockhash`, `bits`, `curtime` and so on) must come from the node.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct GetBlockTemplate {
+    pub height: u32,
+    pub transactions: Vec<GetBlockTemplateTransaction>,
+    #[napi(js_name = "coinbasevalue")]
+    pub coinbase_value: f64,
+    #[napi(js_name = "mintime")]
+    pub min_time: u32,
+    #[napi(js_name = "sizelimit")]
+    pub size_limit: u32,
+    #[napi(js_name = "weightlimit")]
+    pub weight_limit: u32,
+    #[napi(js_name = "sigoplimit")]
+    pub sigop_limit: u32,
+}
+
+/// Export a projected block of mempool uids as a `getblocktemplate` result for the block
+/// after the chain tip.
+pub fn get_block_template(
+    mempool: &Mempool,
+    block: &[u32],
+    transactions: &[TemplateTransaction],
+    max_block_weight: u32,
+) -> Result<GetBlockTemplate, String> {
+    let chain_tip = mempool
+        .chain_tip
+        .ok_or("the chain tip must be set to build a block template")?;
+    let height = chain_tip.height + 1;
+    let mut fees: u64 = 0;
+    let mut entries: Vec<GetBlockTemplateTransaction> = Vec::with_capacity(block.len());
+    let mut template_txs = u32hashmap_with_capacity(transactions.len());
+    for template_tx in transactions {
+        template_txs.insert(template_tx.uid, template_tx);
+    }
+    // 1-based positions, of the transactions before the current one
+    let mut positions = u32hashmap_with_capacity(block.len());
+    for (index, uid) in block.iter().enumerate() {
+        let tx = mempool
+            .get(*uid)
+            .ok_or_else(|| format!("transaction {uid} is not in the mempool"))?;
+        let template_tx = template_txs.get(uid).copied();
+        // parents always come before their children in a projected block
+        let mut depends: Vec<u32> = mempool
+            .parents(*uid)
+            .filter_map(|parent| positions.get(&parent).copied())
+            .collect();
+        depends.sort_unstable();
+        depends.dedup();
+        positions.insert(*uid, index as u32 + 1);
+        fees += tx.fee as u64;
+        entries.push(GetBlockTemplateTransaction {
+            data: template_tx.map(|template_tx| template_tx.data.clone()),
+            txid: template_tx
+                .map(|template_tx| template_tx.txid.clone())
+                .or_else(|| tx.txid.clone()),
+            hash: template_tx.map(|template_tx| {
+                template_tx
+                    .wtxid
+                    .clone()
+                    .unwrap_or_else(|| template_tx.txid.clone())
+            }),
+            depends,
+            fee: tx.fee,
+            sigops: tx.sigops,
+            weight: tx.weight,
+        });
+    }
+
+    Ok(GetBlockTemplate {
+        height,
+        transactions: entries,
+        coinbase_value: (subsidy(height) + fees) as f64,
+        min_time: chain_tip.median_time_past + 1,
+        size_limit: MAX_BLOCK_SERIALIZED_SIZE,
+        weight_limit: max_block_weight,
+        sigop_limit: MAX_BLOCK_SIGOPS_COST,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_block_template;
+    use crate::{
+        block_template::TemplateTransaction, mempool::Mempool, thread_transaction::test_tx,
+        timelock::ChainTip,
+    };
+
+    #[test]
+    fn test_get_block_template() {
+        let mut mempool = Mempool::with_capacity(3);
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(1, 2000.0, 400, vec![]));
+        mempool.insert(test_tx(2, 3000.0, 400, vec![0, 1]));
+        let transactions = vec![TemplateTransaction {
+            uid: 2,
+            txid: "22".repeat(32),
+            wtxid: Some("33".repeat(32)),
+            data: "0200".to_string(),
+        }];
+        assert!(get_block_template(&mempool, &[1, 0, 2], &transactions, 4_000_000).is_err());
+
+        mempool.chain_tip = Some(ChainTip {
+            height: 839_999,
+            median_time_past: 1_700_000_000,
+        });
+        let template = get_block_template(&mempool, &[1, 0, 2], &transactions, 3_996_000)
+            .expect("chain tip is set");
+        assert_eq!(template.height, 840_000);
+        assert_eq!(template.coinbase_value, 312_506_000.0);
+        assert_eq!(template.min_time, 1_700_000_001);
+        // the size limit is in bytes, not weight
+        assert_eq!(template.size_limit, 4_000_000);
+        assert_eq!(template.weight_limit, 3_996_000);
+        assert_eq!(template.transactions[2].depends, vec![1, 2]);
+        assert!(template.transactions[0].depends.is_empty());
+        assert_eq!(template.transactions[0].txid, Some(format!("{:064x}", 1)));
+        assert_eq!(template.transactions[0].data, None);
+        assert_eq!(template.transactions[2].hash, Some("33".repeat(32)));
+        assert_eq!(template.transactions[2].data, Some("0200".to_string()));
+    }
+}
@@ -0,0 +1,93 @@
+use napi_derive::napi;
+
+/// Totals for the transactions with an effective feerate from `fee_rate` (inclusive)
+/// up to the `fee_rate` of the next bucket (exclusive).
+///
+/// The first bucket also collects everything below its `fee_rate`,
+/// and the last bucket everything above.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct HistogramBucket {
+    pub fee_rate: f64,
+    pub vsize: f64,
+    pub count: u32,
+    pub fee: f64,
+}
+
+/// Accumulates a vsize-per-feerate histogram of the mempool, bucketed by effective feerate.
+///
+/// Ported from `Statistics.$createStatistics` in the mempool backend's
+/// [statistics.ts](https://github.com/mempool/mempool/blob/master/backend/src/api/statistics/statistics.ts).
+pub struct FeeHistogram {
+    buckets: Vec<HistogramBucket>,
+}
+
+impl FeeHistogram {
+    pub fn new(bucket_edges: &[f64]) -> Self {
+        let mut edges: Vec<f64> = bucket_edges
+            .iter()
+            .copied()
+            .filter(|edge| !edge.is_nan())
+            .collect();
+        edges.sort_unstable_by(f64::total_cmp);
+        edges.dedup();
+        Self {
+            buckets: edges
+                .into_iter()
+                .map(|fee_rate| HistogramBucket {
+                    fee_rate,
+                    vsize: 0.0,
+                    count: 0,
+                    fee: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn add(&mut self, effective_fee_per_vsize: f64, weight: u32, fee: f64) {
+        let index = self
+            .buckets
+            .partition_point(|bucket| bucket.fee_rate <= effective_fee_per_vsize)
+            .saturating_sub(1);
+        if let Some(bucket) = self.buckets.get_mut(index) {
+            bucket.vsize += f64::from(weight) / 4.0;
+            bucket.count += 1;
+            bucket.fee += fee;
+        }
+    }
+
+    pub fn into_buckets(self) -> Vec<HistogramBucket> {
+        self.buckets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeHistogram;
+
+    #[test]
+    fn test_bucket_edges() {
+        let mut histogram = FeeHistogram::new(&[10.0, 1.0, 5.0, f64::NAN, 5.0]);
+        // lower edges are inclusive
+        histogram.add(5.0, 400, 500.0);
+        histogram.add(4.99, 400, 499.0);
+        // out of range rates go to the first or last bucket
+        histogram.add(0.5, 400, 50.0);
+        histogram.add(100.0, 401, 10_025.0);
+
+        let buckets = histogram.into_buckets();
+        let fee_rates: Vec<f64> = buckets.iter().map(|bucket| bucket.fee_rate).collect();
+        assert_eq!(fee_rates, vec![1.0, 5.0, 10.0]);
+        let counts: Vec<u32> = buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, vec![2, 1, 1]);
+        // vsize is weight / 4, without rounding up
+        let vsizes: Vec<f64> = buckets.iter().map(|bucket| bucket.vsize).collect();
+        assert_eq!(vsizes, vec![200.0, 100.0, 100.25]);
+        let fees: Vec<f64> = buckets.iter().map(|bucket| bucket.fee).collect();
+        assert_eq!(fees, vec![549.0, 500.0, 10_025.0]);
+
+        let mut histogram = FeeHistogram::new(&[]);
+        histogram.add(1.0, 400, 100.0);
+        assert!(histogram.into_buckets().is_empty());
+    }
+}
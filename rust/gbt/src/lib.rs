@@ -5,25 +5,56 @@
 #![allow(clippy::cast_possible_truncation)]
 #![allow(clippy::cast_sign_loss)]
 #![allow(clippy::float_cmp)]
+// tests are long lists of cases
+#![cfg_attr(test, allow(clippy::cognitive_complexity))]
 
-use napi::bindgen_prelude::Result;
+use block_template::{BlockTemplate, TemplateTransaction};
+use cpfp::{BlockCpfpTask, CpfpInfo};
+use eviction::EvictionResult;
+use expiry::ExpiringTransaction;
+use explain::Explanation;
+use feerate_diagram::{DiagramPoint, FeerateDiagram};
+use getblocktemplate::GetBlockTemplate;
+use histogram::HistogramBucket;
+use mempool::{Eviction, Mempool, MempoolChanges, RemovalMode, SpendConflict};
+use thread_outpoint::ThreadOutpoint;
+use policy::PolicyFilter;
+use rbf::RbfEvaluation;
+use template_comparison::{NodeTemplateDiff, TemplateComparison, TemplateDiff};
+use napi::bindgen_prelude::{AsyncTask, Result};
 use napi_derive::napi;
 use thread_transaction::ThreadTransaction;
-use thread_acceleration::ThreadAcceleration;
+use thread_acceleration::{fee_deltas, FeeDeltas, ThreadAcceleration};
+use timelock::ChainTip;
 use tracing::{debug, info, trace};
 use tracing_log::LogTracer;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 mod audit_transaction;
+mod block_template;
+mod cpfp;
+mod eviction;
+mod expiry;
+mod explain;
+mod feerate_diagram;
 mod gbt;
+mod getblocktemplate;
+mod histogram;
+mod mempool;
+mod policy;
+mod rbf;
+mod template_comparison;
+mod thread_outpoint;
 mod thread_transaction;
 mod thread_acceleration;
+mod timelock;
 mod u32_hasher_types;
 
-use u32_hasher_types::{u32hashmap_with_capacity, U32HasherState};
+use u32_hasher_types::U32HasherState;
 
 /// This is the initial capacity of the `GbtGenerator` struct's inner `HashMap`.
 ///
@@ -34,7 +65,7 @@ type ThreadTransactionsMap = HashMap<u32, ThreadTransaction, U32HasherState>;
 
 #[napi]
 pub struct GbtGenerator {
-    thread_transactions: Arc<Mutex<ThreadTransactionsMap>>,
+    thread_transactions: Arc<Mutex<Mempool>>,
     max_block_weight: u32,
     max_blocks: usize,
 }
@@ -70,12 +101,15 @@ impl GbtGenerator {
     pub fn new(max_block_weight: u32, max_blocks: u32) -> Self {
         debug!("Created new GbtGenerator");
         Self {
-            thread_transactions: Arc::new(Mutex::new(u32hashmap_with_capacity(STARTING_CAPACITY))),
+            thread_transactions: Arc::new(Mutex::new(Mempool::with_capacity(STARTING_CAPACITY))),
             max_block_weight,
             max_blocks: max_blocks as usize,
         }
     }
 
+    /// If `accelerations` are given they replace the stored accelerations,
+    /// otherwise the stored accelerations are used.
+    ///
     /// # Errors
     ///
     /// Rejects if the thread panics or if the Mutex is poisoned.
@@ -83,25 +117,40 @@ impl GbtGenerator {
     pub async fn make(
         &self,
         mempool: Vec<ThreadTransaction>,
-        accelerations: Vec<ThreadAcceleration>,
+        accelerations: Option<Vec<ThreadAcceleration>>,
         max_uid: u32,
+        options: Option<GbtOptions>,
     ) -> Result<GbtResult> {
         trace!("make: Current State {:#?}", self.thread_transactions);
         run_task(
             Arc::clone(&self.thread_transactions),
-            accelerations,
             max_uid as usize,
             self.max_block_weight,
             self.max_blocks,
+            options.unwrap_or_default(),
             move |map| {
+                if let Some(accelerations) = accelerations {
+                    map.set_fee_deltas(&accelerations);
+                }
+                let uids: Vec<u32> = mempool.iter().map(|tx| tx.uid).collect();
                 for tx in mempool {
-                    map.insert(tx.uid, tx);
+                    map.insert(tx);
+                }
+                MempoolChanges {
+                    conflicts: map.conflicts(&uids),
+                    cascaded: None,
                 }
             },
         )
         .await
     }
 
+    /// Descendants of the removed transactions are kept, removed or orphaned according to
+    /// `options.removalMode`, which defaults to keeping them.
+    ///
+    /// If `accelerations` are given they replace the stored accelerations,
+    /// otherwise the stored accelerations are used.
+    ///
     /// # Errors
     ///
     /// Rejects if the thread panics or if the Mutex is poisoned.
@@ -110,27 +159,442 @@ impl GbtGenerator {
         &self,
         new_txs: Vec<ThreadTransaction>,
         remove_txs: Vec<u32>,
-        accelerations: Vec<ThreadAcceleration>,
+        accelerations: Option<Vec<ThreadAcceleration>>,
         max_uid: u32,
+        options: Option<GbtOptions>,
     ) -> Result<GbtResult> {
         trace!("update: Current State {:#?}", self.thread_transactions);
+        let options = options.unwrap_or_default();
+        let removal_mode = options.removal_mode.unwrap_or(RemovalMode::Keep);
         run_task(
             Arc::clone(&self.thread_transactions),
-            accelerations,
             max_uid as usize,
             self.max_block_weight,
             self.max_blocks,
+            options,
             move |map| {
+                if let Some(accelerations) = accelerations {
+                    map.set_fee_deltas(&accelerations);
+                }
+                let uids: Vec<u32> = new_txs.iter().map(|tx| tx.uid).collect();
                 for tx in new_txs {
-                    map.insert(tx.uid, tx);
+                    map.insert(tx);
                 }
-                for txid in &remove_txs {
-                    map.remove(txid);
+                let cascaded = map.remove_all(&remove_txs, removal_mode);
+                MempoolChanges {
+                    conflicts: map.conflicts(&uids),
+                    cascaded: (removal_mode != RemovalMode::Keep).then_some(cascaded),
                 }
             },
         )
         .await
     }
+
+    /// Set the best block the projected blocks are built on
+    ///
+    /// Once set, transactions are held back from projected blocks until their `enforcedLocktime`,
+    /// `relativeLockHeight` and `relativeLockTime` are satisfied, assuming each block
+    /// advances the median-time-past by 10 minutes. Results also include the subsidy
+    /// and fees of each projected block in `blockRewards`, which the `tipHeight` option
+    /// gives without enforcing timelocks.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn set_chain_tip(&self, height: u32, median_time_past: u32) -> Result<()> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.chain_tip = Some(ChainTip {
+                height,
+                median_time_past,
+            });
+        })
+        .await
+    }
+
+    /// Set the lowest package feerate worth mining in sats/vB, like Core's `blockmintxfee`,
+    /// or clear it with null
+    ///
+    /// Packages below it are reported in `belowMinFee` instead of any block.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn set_min_fee_rate(&self, min_fee_rate: Option<f64>) -> Result<()> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.min_fee_rate = min_fee_rate;
+        })
+        .await
+    }
+
+    /// Add or update the fee delta of one transaction, which may not have arrived yet
+    ///
+    /// Only the rates of changed accelerations and their relatives are reported
+    /// by the next `make` or `update`.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn add_acceleration(&self, acceleration: ThreadAcceleration) -> Result<()> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.set_fee_delta(acceleration.uid, acceleration.delta);
+        })
+        .await
+    }
+
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn remove_acceleration(&self, uid: u32) -> Result<()> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.remove_fee_delta(uid);
+        })
+        .await
+    }
+
+    /// Replace all of the stored accelerations
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn set_accelerations(&self, accelerations: Vec<ThreadAcceleration>) -> Result<()> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.set_fee_deltas(&accelerations);
+        })
+        .await
+    }
+
+    /// Remove the transactions mined in a new block, along with any mempool transactions
+    /// which conflict with the block and all of their descendants
+    ///
+    /// `spent` lists any other outpoints spent in the block, such as by transactions which
+    /// were never in the mempool.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn block_connected(
+        &self,
+        mined: Vec<u32>,
+        spent: Vec<ThreadOutpoint>,
+    ) -> Result<Vec<Eviction>> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.block_connected(&mined, spent)
+        })
+        .await
+    }
+
+    /// Re-add the transactions from a block disconnected by a reorg,
+    /// resolving to any outpoints which are now double-spent
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn block_disconnected(
+        &self,
+        transactions: Vec<ThreadTransaction>,
+    ) -> Result<Option<Vec<SpendConflict>>> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            mempool.block_disconnected(transactions)
+        })
+        .await
+    }
+
+    /// Calculate the CPFP relatives, chunks and effective feerate of a single mempool transaction
+    ///
+    /// Resolves to null if the transaction is not in the mempool.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn get_cpfp_info(
+        &self,
+        uid: u32,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+    ) -> Result<Option<CpfpInfo>> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            cpfp::cpfp_info(mempool, &fee_deltas, uid)
+        })
+        .await
+    }
+
+    /// Compare two block templates built over the current mempool by their feerate diagrams
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn compare_templates(
+        &self,
+        expected: Vec<u32>,
+        actual: Vec<u32>,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+    ) -> Result<TemplateComparison> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            template_comparison::compare_templates(mempool, &fee_deltas, &expected, &actual)
+        })
+        .await
+    }
+
+    /// Simulate trimming the current mempool down to `size_limit` vbytes by descendant score,
+    /// resolving to the evicted transactions and the resulting rolling minimum feerate
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn simulate_eviction(
+        &self,
+        size_limit: f64,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+    ) -> Result<EvictionResult> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            eviction::trim_to_size(mempool, &fee_deltas, size_limit)
+        })
+        .await
+    }
+
+    /// List the transactions which will expire from the mempool within `horizon` seconds of
+    /// `now`, with the descendants expiring alongside them and their projected blocks
+    ///
+    /// `expiry` defaults to Core's 336 hour `-mempoolexpiry`. Only transactions given with
+    /// a `firstSeen` time can expire.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn get_expiring(
+        &self,
+        now: f64,
+        horizon: f64,
+        expiry: Option<f64>,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+    ) -> Result<Vec<ExpiringTransaction>> {
+        let max_block_weight = self.max_block_weight;
+        let max_blocks = self.max_blocks;
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            expiry::expiring(
+                mempool,
+                &fee_deltas,
+                now,
+                horizon,
+                expiry.unwrap_or(expiry::DEFAULT_MEMPOOL_EXPIRY),
+                max_block_weight,
+                max_blocks,
+            )
+        })
+        .await
+    }
+
+    /// Trace how a fresh gbt run over the current mempool selects one transaction: each time
+    /// its package was considered, the packages selected ahead of it and each block's cutoff
+    ///
+    /// Only the `now`, `propagationDelay` and `policy` options affect the selection.
+    /// Resolves to null if the transaction is not in the mempool.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn explain(
+        &self,
+        uid: u32,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+        options: Option<GbtOptions>,
+    ) -> Result<Option<Explanation>> {
+        let max_block_weight = self.max_block_weight;
+        let max_blocks = self.max_blocks;
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            explain::explain(
+                mempool,
+                &fee_deltas,
+                uid,
+                max_block_weight,
+                max_blocks,
+                &options.unwrap_or_default(),
+            )
+        })
+        .await
+    }
+
+    /// Assemble a complete block template at `height` from a projected block of uids,
+    /// with a segwit coinbase paying the subsidy and fees to `payout_script`
+    ///
+    /// `transactions` carry the full data of every transaction in the block.
+    /// `coinbase_script` and `payout_script` are hex, and the coinbase script must start
+    /// with the BIP34 height and be 2 to 100 bytes long.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if a transaction is missing from the mempool or from `transactions`,
+    /// if any hex is invalid, if the coinbase script is invalid, if the thread panics
+    /// or if the Mutex is poisoned.
+    #[napi]
+    pub async fn assemble_template(
+        &self,
+        block: Vec<u32>,
+        transactions: Vec<TemplateTransaction>,
+        height: u32,
+        coinbase_script: String,
+        payout_script: String,
+    ) -> Result<BlockTemplate> {
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            block_template::assemble(
+                mempool,
+                &block,
+                &transactions,
+                height,
+                &coinbase_script,
+                &payout_script,
+            )
+        })
+        .await?
+        .map_err(napi::Error::from_reason)
+    }
+
+    /// Export the first block of a fresh projection over the current mempool as a BIP22/BIP23
+    /// `getblocktemplate` result for the block after the chain tip
+    ///
+    /// `transactions` optionally carry the full data of the transactions in the block.
+    /// Only the `now`, `propagationDelay` and `policy` options affect the selection.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the chain tip is not set, if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn get_block_template(
+        &self,
+        transactions: Option<Vec<TemplateTransaction>>,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+        options: Option<GbtOptions>,
+    ) -> Result<GetBlockTemplate> {
+        let max_block_weight = self.max_block_weight;
+        let max_blocks = self.max_blocks;
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            let options = options.unwrap_or_default();
+            let result = gbt::project(
+                mempool,
+                &fee_deltas,
+                max_block_weight,
+                max_blocks,
+                &options.selection_only(),
+            );
+            getblocktemplate::get_block_template(
+                mempool,
+                result.blocks.first().map_or(&[], Vec::as_slice),
+                transactions.as_deref().unwrap_or_default(),
+                max_block_weight,
+            )
+        })
+        .await?
+        .map_err(napi::Error::from_reason)
+    }
+
+    /// Diff a node's `getblocktemplate` result against the first block of a fresh projection
+    /// over the current mempool, to check the accuracy of the projection
+    ///
+    /// The node's transactions are matched to mempool transactions by their `txid`.
+    /// Only the `now`, `propagationDelay` and `policy` options affect the selection.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the thread panics or if the Mutex is poisoned.
+    #[napi]
+    pub async fn diff_block_template(
+        &self,
+        template: GetBlockTemplate,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+        options: Option<GbtOptions>,
+    ) -> Result<NodeTemplateDiff> {
+        let max_block_weight = self.max_block_weight;
+        let max_blocks = self.max_blocks;
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            let options = options.unwrap_or_default();
+            let result = gbt::project(
+                mempool,
+                &fee_deltas,
+                max_block_weight,
+                max_blocks,
+                &options.selection_only(),
+            );
+            template_comparison::diff_node_template(
+                mempool,
+                result.blocks.first().map_or(&[], Vec::as_slice),
+                &template,
+            )
+        })
+        .await
+    }
+
+    /// Evaluate a replacement package against the current mempool using the package RBF rules
+    ///
+    /// `conflicts` are the uids of the mempool transactions the package double-spends.
+    ///
+    /// # Errors
+    ///
+    /// Rejects if the mempool has no uids left to number the package, if the thread panics
+    /// or if the Mutex is poisoned.
+    #[napi]
+    pub async fn evaluate_replacement(
+        &self,
+        package: Vec<ThreadTransaction>,
+        conflicts: Vec<u32>,
+        accelerations: Option<Vec<ThreadAcceleration>>,
+    ) -> Result<RbfEvaluation> {
+        let max_block_weight = self.max_block_weight;
+        let max_blocks = self.max_blocks;
+        run_query(Arc::clone(&self.thread_transactions), move |mempool| {
+            let fee_deltas = query_fee_deltas(mempool, accelerations.as_deref());
+            rbf::evaluate_replacement(
+                mempool,
+                package,
+                &conflicts,
+                &fee_deltas,
+                max_block_weight,
+                max_blocks,
+            )
+        })
+        .await?
+        .map_err(napi::Error::from_reason)
+    }
+}
+
+/// Calculate effective fee rates and CPFP clusters for the transactions in a mined block.
+#[napi(ts_return_type = "Promise<BlockCpfpResult>")]
+#[must_use]
+pub fn block_cpfp(
+    transactions: Vec<ThreadTransaction>,
+    accelerations: Vec<ThreadAcceleration>,
+) -> AsyncTask<BlockCpfpTask> {
+    debug!("Spawning block cpfp task...");
+    AsyncTask::new(BlockCpfpTask {
+        transactions,
+        accelerations,
+    })
+}
+
+/// The total fee available in the first `vsize` vbytes of a feerate diagram
+/// from `GbtResult.diagram`.
+#[napi]
+#[must_use]
+#[allow(clippy::needless_pass_by_value)]
+pub fn diagram_fee_at(diagram: Vec<DiagramPoint>, vsize: f64) -> f64 {
+    FeerateDiagram::from_points(diagram).fee_at(vsize)
 }
 
 /// The result from calling the gbt function.
@@ -138,36 +602,214 @@ impl GbtGenerator {
 /// This tuple contains the following:
 ///        blocks: A 2D Vector of transaction IDs (u32), the inner Vecs each represent a block.
 /// block_weights: A Vector of total weights per block.
+///  block_scores: The range of package scores around the cutoff of each block, see `BlockScores`
 ///      clusters: A 2D Vector of transaction IDs representing clusters of dependent mempool transactions
+///   cluster_ids: (optional) The id of the connected component each of `clusters` belongs to,
+///                which is kept across `update` calls while the component only grows,
+///                or null if it has none
+///   tx_clusters: (optional) Tuples of transaction IDs and those cluster ids, for every
+///                transaction with an in-mempool relative
 ///         rates: A Vector of tuples containing transaction IDs (u32) and effective fee per vsize (f64)
+///      overflow: A Vector of transaction IDs which did not fit in any block. Transactions
+///                below the minimum feerate are only in `below_min_fee`, so together with
+///                `blocks` those cover the whole mempool.
+///        chunks: (optional) Every package in selection order, see `Chunk`
+///     relatives: (optional) The ancestors and descendants of every clustered transaction,
+///                see `Relatives`
+///     histogram: (optional) The vsize, count and fees of the mempool per effective feerate bucket
+///       diagram: (optional) The cumulative fee, including fee deltas, vs cumulative vsize
+///                of every selected package, in selection order
+///     conflicts: (optional) Outpoints double-spent by the transactions added in this call,
+///                if they were given with `prevouts`
+///      cascaded: (optional) Descendants removed or orphaned along with `remove_txs`,
+///                unless the removal mode is `keep`
+///   policy_diff: (optional) How the first block differs from the one built without the
+///                `policy` filter, if one was given
+/// below_min_fee: (optional) Tuples of transaction IDs and package feerates which were left
+///                out of every block for paying less than the generator's minimum feerate,
+///                if one is set
+///    unmineable: Every transaction in `overflow` or `below_min_fee`, with the reason
+///                it was left out of every block
+///   explanation: (optional) How the selection treated the `explain` transaction
+/// block_rewards: (optional) The subsidy, fees and revenue of each block, see `BlockReward`,
+///                if the `tip_height` option or the generator's chain tip is set
 #[napi(constructor)]
 pub struct GbtResult {
     pub blocks: Vec<Vec<u32>>,
     pub block_weights: Vec<u32>,
+    pub block_scores: Vec<BlockScores>,
     pub clusters: Vec<Vec<u32>>,
+    pub cluster_ids: Option<Vec<Option<u32>>>,
+    pub tx_clusters: Option<Vec<Vec<u32>>>,
     pub rates: Vec<Vec<f64>>, // Tuples not supported. u32 fits inside f64
     pub overflow: Vec<u32>,
+    pub chunks: Option<Vec<Chunk>>,
+    pub relatives: Option<Vec<Relatives>>,
+    pub histogram: Option<Vec<HistogramBucket>>,
+    pub diagram: Option<Vec<DiagramPoint>>,
+    pub conflicts: Option<Vec<SpendConflict>>,
+    pub cascaded: Option<Vec<u32>>,
+    pub policy_diff: Option<TemplateDiff>,
+    pub below_min_fee: Option<Vec<Vec<f64>>>, // Tuples not supported. u32 fits inside f64
+    pub unmineable: Vec<Unmineable>,
+    pub explanation: Option<Explanation>,
+    pub block_rewards: Option<Vec<BlockReward>>,
+}
+
+/// Optional extra outputs for a call to the gbt function.
+///
+/// Everything defaults to off, so callers only pay for what they ask for.
+#[derive(Clone, Debug, Default)]
+#[napi(object)]
+pub struct GbtOptions {
+    /// Include every selected package in `GbtResult.chunks`
+    pub chunks: Option<bool>,
+    /// Include the relatives of every clustered transaction in `GbtResult.relatives`
+    pub relatives: Option<bool>,
+    /// Bucket the whole mempool by effective feerate into `GbtResult.histogram`,
+    /// using these feerates as the lower edge of each bucket
+    pub histogram_buckets: Option<Vec<f64>>,
+    /// Include the feerate diagram of the projected blocks in `GbtResult.diagram`
+    pub diagram: Option<bool>,
+    /// What `update` does with the descendants of removed transactions, defaults to `keep`.
+    /// Orphaned transactions are left out of the blocks and reported in the overflow.
+    pub removal_mode: Option<RemovalMode>,
+    /// The current time in unix seconds, for `propagation_delay`
+    pub now: Option<f64>,
+    /// Seconds a transaction needs to reach miners. If set along with `now`, transactions
+    /// first seen more recently are left out of the first projected block.
+    pub propagation_delay: Option<f64>,
+    /// Build the blocks as a miner with this transaction selection policy would.
+    /// Filtered out transactions are reported in the overflow.
+    pub policy: Option<PolicyFilter>,
+    /// Trace how the selection treats this transaction into `GbtResult.explanation`
+    pub explain: Option<u32>,
+    /// Assign stable ids to the mempool's clusters, and include them in
+    /// `GbtResult.clusterIds` and `GbtResult.txClusters`
+    pub cluster_ids: Option<bool>,
+    /// The height of the best block, to include `GbtResult.blockRewards` without setting
+    /// the generator's chain tip, which also enforces timelocks. Defaults to the chain tip's.
+    pub tip_height: Option<u32>,
+}
+
+impl GbtOptions {
+    /// Only the options which change which transactions are selected into the blocks, for
+    /// queries which project the blocks on the side and must not pay for the extra outputs.
+    #[must_use]
+    pub fn selection_only(&self) -> Self {
+        Self {
+            now: self.now,
+            propagation_delay: self.propagation_delay,
+            policy: self.policy.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+/// A package of transactions selected together by the gbt algorithm.
+///
+/// `uids` are ordered by ascending ancestor count, so parents always come before their children.
+/// `fee` includes any acceleration fee deltas, and `vsize` is sigop-adjusted.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct Chunk {
+    pub uids: Vec<u32>,
+    pub fee: f64,
+    pub vsize: f64,
+    pub effective_fee_per_vsize: f64,
+}
+
+/// The package scores (ancestor feerates, in sats/vB) on either side of a block's cutoff.
+///
+/// `min_accepted` is the lowest score selected into the block, and `max_rejected` the highest
+/// score held back because it did not fit in the remaining weight or sigops. Either is missing
+/// if no package was, and the final block never rejects anything for space.
+#[derive(Clone, Debug, Default)]
+#[napi(object)]
+pub struct BlockScores {
+    pub min_accepted: Option<f64>,
+    pub max_rejected: Option<f64>,
+}
+
+/// The miner revenue of a projected block, in sats.
+///
+/// `fees` exclude any accelerations, which are reported apart in `fee_deltas`, so `fees` plus
+/// `fee_deltas` is the basis of the block's chunk and diagram fees. `fee_share` is the fraction
+/// of the block's reward paid in fees, and `cumulative_revenue` is the total reward of this
+/// block and every one before it.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct BlockReward {
+    pub height: u32,
+    pub subsidy: f64,
+    pub fees: f64,
+    pub fee_deltas: f64,
+    pub fee_share: f64,
+    pub cumulative_revenue: f64,
+}
+
+/// Why a transaction was left out of every projected block.
+#[derive(Debug, PartialEq, Eq)]
+#[napi(string_enum = "kebab-case")]
+pub enum UnmineableReason {
+    /// Its package is too heavy to fit in an empty block
+    Weight,
+    /// Its package has too many sigops to fit in an empty block
+    Sigops,
+    /// Its package can not be mined within the projected blocks because of its timelocks
+    Timelocked,
+    /// Its package was first seen too recently to have reached miners
+    /// before the end of the projected blocks
+    Unpropagated,
+    /// Its package pays less than the generator's minimum feerate
+    BelowMinFee,
+    /// It has a missing ancestor
+    Orphaned,
+    /// It or one of its ancestors was filtered out by the `policy` option
+    Filtered,
+}
+
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct Unmineable {
+    pub uid: u32,
+    pub reason: UnmineableReason,
+}
+
+/// The relatives of a transaction within the package it was selected in.
+///
+/// `ancestors` and `descendants` are ordered by ascending ancestor count.
+/// `best_descendant` is the root of the package (the child paying for it),
+/// and is only missing for the root itself.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct Relatives {
+    pub uid: u32,
+    pub ancestors: Vec<u32>,
+    pub descendants: Vec<u32>,
+    pub best_descendant: Option<u32>,
 }
 
 /// All on another thread, this runs an arbitrary task in between
 /// taking the lock and running gbt.
 ///
-/// Rather than filling / updating the `HashMap` on the main thread,
-/// this allows for `HashMap` modifying tasks to be run before running and returning gbt results.
+/// Rather than filling / updating the `Mempool` on the main thread,
+/// this allows for `Mempool` modifying tasks to be run before running and returning gbt results.
 ///
-/// `thread_transactions` is a cloned `Arc` of the `Mutex` for the `HashMap` state.
+/// `thread_transactions` is a cloned `Arc` of the `Mutex` for the `Mempool` state.
 /// `callback` is a `'static + Send` `FnOnce` closure/function that takes a mutable reference
-/// to the `HashMap` as the only argument. (A move closure is recommended to meet the bounds)
+/// to the `Mempool` as the only argument, and reports any `MempoolChanges` to add to the results.
+/// (A move closure is recommended to meet the bounds)
 async fn run_task<F>(
-    thread_transactions: Arc<Mutex<ThreadTransactionsMap>>,
-    accelerations: Vec<ThreadAcceleration>,
+    thread_transactions: Arc<Mutex<Mempool>>,
     max_uid: usize,
     max_block_weight: u32,
     max_blocks: usize,
+    options: GbtOptions,
     callback: F,
 ) -> Result<GbtResult>
 where
-    F: FnOnce(&mut ThreadTransactionsMap) + Send + 'static,
+    F: FnOnce(&mut Mempool) -> MempoolChanges + Send + 'static,
 {
     debug!("Spawning thread...");
     let handle = napi::tokio::task::spawn_blocking(move || {
@@ -178,16 +820,25 @@ where
         let mut map = thread_transactions
             .lock()
             .map_err(|_| napi::Error::from_reason("THREAD_TRANSACTIONS Mutex poisoned"))?;
-        callback(&mut map);
+        let changes = callback(&mut map);
+        let report_cluster_ids = options.cluster_ids.unwrap_or(false);
+        if report_cluster_ids {
+            map.refresh_cluster_ids();
+        }
 
         info!("Starting gbt algorithm for {} elements...", map.len());
-        let result = gbt::gbt(
-            &mut map,
-            &accelerations,
-            max_uid,
-            max_block_weight,
-            max_blocks as usize,
-        );
+        let mempool = &mut *map;
+        let result = gbt::run(mempool, max_uid, max_block_weight, max_blocks, &options);
+        let (cluster_ids, tx_clusters) = if report_cluster_ids {
+            let cluster_ids = result
+                .clusters
+                .iter()
+                .map(|cluster| cluster.first().and_then(|uid| mempool.cluster_id(*uid)))
+                .collect();
+            (Some(cluster_ids), Some(mempool.cluster_ids()))
+        } else {
+            (None, None)
+        };
         info!("Finished gbt algorithm for {} elements...", map.len());
 
         debug!(
@@ -196,7 +847,46 @@ where
         );
         drop(map);
 
-        Ok(result)
+        Ok(GbtResult {
+            conflicts: changes.conflicts,
+            cascaded: changes.cascaded,
+            cluster_ids,
+            tx_clusters,
+            ..result
+        })
+    });
+
+    handle
+        .await
+        .map_err(|_| napi::Error::from_reason("thread panicked"))?
+}
+
+/// The fee deltas for a query: the given accelerations if any, otherwise the stored ones.
+fn query_fee_deltas<'a>(
+    mempool: &'a Mempool,
+    accelerations: Option<&[ThreadAcceleration]>,
+) -> Cow<'a, FeeDeltas> {
+    accelerations.map_or(Cow::Borrowed(&mempool.fee_deltas), |accelerations| {
+        Cow::Owned(fee_deltas(accelerations))
+    })
+}
+
+/// All on another thread, this runs an arbitrary query against the mempool state
+/// without running gbt on it.
+///
+/// `callback` is a `'static + Send` `FnOnce` closure/function that takes a mutable reference
+/// to the `Mempool` as the only argument, and returns the query result.
+async fn run_query<F, T>(thread_transactions: Arc<Mutex<Mempool>>, callback: F) -> Result<T>
+where
+    F: FnOnce(&mut Mempool) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    debug!("Spawning query thread...");
+    let handle = napi::tokio::task::spawn_blocking(move || {
+        let mut map = thread_transactions
+            .lock()
+            .map_err(|_| napi::Error::from_reason("THREAD_TRANSACTIONS Mutex poisoned"))?;
+        Ok(callback(&mut map))
     });
 
     handle
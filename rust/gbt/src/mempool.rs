@@ -0,0 +1,881 @@
+use napi_derive::napi;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    thread_acceleration::{fee_deltas, FeeDeltas, ThreadAcceleration},
+    thread_outpoint::ThreadOutpoint,
+    thread_transaction::ThreadTransaction,
+    timelock::ChainTip,
+    u32_hasher_types::{u32hashmap_with_capacity, u32hashset_new, U32HasherState},
+    ThreadTransactionsMap,
+};
+
+type ChildrenMap = HashMap<u32, HashSet<u32, U32HasherState>, U32HasherState>;
+/// spending uids by prevout txid, then by prevout vout
+type SpendMap = HashMap<String, HashMap<u32, Vec<u32>, U32HasherState>>;
+
+/// An outpoint spent by more than one mempool transaction.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct SpendConflict {
+    pub txid: String,
+    pub vout: u32,
+    pub uids: Vec<u32>,
+}
+
+/// Why a transaction was removed from the mempool when a block was connected.
+#[derive(Debug, PartialEq, Eq)]
+#[napi(string_enum = "kebab-case")]
+pub enum EvictionReason {
+    /// Confirmed in the block
+    Mined,
+    /// Spends an outpoint which was spent in the block
+    Conflict,
+    /// Descends from a conflicting transaction
+    ConflictDescendant,
+}
+
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct Eviction {
+    pub uid: u32,
+    pub reason: EvictionReason,
+}
+
+/// What happens to the descendants of a transaction removed by `update`.
+#[derive(Debug, PartialEq, Eq)]
+#[napi(string_enum = "kebab-case")]
+pub enum RemovalMode {
+    /// Leave them in the mempool as if the removed parent had been confirmed
+    Keep,
+    /// Remove them along with the parent
+    Cascade,
+    /// Leave them in the mempool, but flag them as orphans which can not be mined
+    Orphan,
+}
+
+/// Side effects of changing the mempool state, reported alongside the gbt results.
+#[derive(Debug, Default)]
+pub struct MempoolChanges {
+    /// Outpoints double-spent by any of the changed transactions,
+    /// or `None` if none of them carried their `prevouts`
+    pub conflicts: Option<Vec<SpendConflict>>,
+    /// Descendants removed or orphaned along with the removed transactions,
+    /// or `None` if removals were not cascaded
+    pub cascaded: Option<Vec<u32>>,
+}
+
+/// The mempool state held by a `GbtGenerator` in between calls.
+///
+/// Alongside the transactions themselves this keeps a reverse index of in-mempool children,
+/// so the relatives of a transaction can be found without rebuilding the whole graph,
+/// and a map of the outpoints spent by transactions which provide their `prevouts`.
+///
+/// Children are indexed by the parent uid listed in their `inputs`, whether or not that
+/// parent is currently in the mempool, so the links are restored if the parent is re-added.
+///
+/// `orphans` are transactions left behind by a parent removed with `RemovalMode::Orphan`,
+/// along with all of their descendants. They stay orphaned until every such parent is re-added
+/// or mined, whatever happens to their other parents.
+///
+/// `fee_deltas` persist between calls, and may be set before their transaction arrives,
+/// like Core's `prioritisetransaction`. They are only dropped once the transaction is mined.
+///
+/// Every connected component of two or more transactions carries a cluster id, which is
+/// only reassigned by `refresh_cluster_ids`.
+#[derive(Debug)]
+pub struct Mempool {
+    pub transactions: ThreadTransactionsMap,
+    pub orphans: HashSet<u32, U32HasherState>,
+    pub fee_deltas: FeeDeltas,
+    /// The best block the projected blocks build on, used to enforce timelocks if set
+    pub chain_tip: Option<ChainTip>,
+    /// The lowest package feerate worth mining, in sats/vB, if any
+    pub min_fee_rate: Option<f64>,
+    /// uids whose fee delta changed since the last call to `take_dirty`
+    changed_fee_deltas: HashSet<u32, U32HasherState>,
+    /// cluster id by uid, for every transaction with an in-mempool relative
+    cluster_ids: HashMap<u32, u32, U32HasherState>,
+    /// member count by cluster id
+    cluster_sizes: HashMap<u32, usize, U32HasherState>,
+    next_cluster_id: u32,
+    /// parents removed with `RemovalMode::Orphan` by uid, for every transaction spending them
+    missing_parents: HashMap<u32, Vec<u32>, U32HasherState>,
+    children: ChildrenMap,
+    spends: SpendMap,
+}
+
+impl Mempool {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            transactions: u32hashmap_with_capacity(capacity),
+            orphans: u32hashset_new(),
+            fee_deltas: u32hashmap_with_capacity(0),
+            chain_tip: None,
+            min_fee_rate: None,
+            changed_fee_deltas: u32hashset_new(),
+            cluster_ids: u32hashmap_with_capacity(0),
+            cluster_sizes: u32hashmap_with_capacity(0),
+            next_cluster_id: 0,
+            missing_parents: u32hashmap_with_capacity(0),
+            children: u32hashmap_with_capacity(capacity),
+            spends: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    #[inline]
+    pub fn get(&self, uid: u32) -> Option<&ThreadTransaction> {
+        self.transactions.get(&uid)
+    }
+
+    #[inline]
+    pub fn contains(&self, uid: u32) -> bool {
+        self.transactions.contains_key(&uid)
+    }
+
+    pub fn insert(&mut self, tx: ThreadTransaction) {
+        let uid = tx.uid;
+        // missing parents which this version still spends
+        let missing_parents: Vec<u32> = self
+            .missing_parents
+            .get(&uid)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|parent| !self.contains(*parent) && tx.inputs.contains(parent))
+            .collect();
+        let orphaned = !missing_parents.is_empty()
+            || tx.inputs.iter().any(|input| self.orphans.contains(input));
+        // drop the links of any previous version of this transaction
+        if self.remove(uid).is_none() && self.fee_deltas.contains_key(&uid) {
+            // a pending fee delta applies for the first time
+            self.changed_fee_deltas.insert(uid);
+        }
+        for parent in &tx.inputs {
+            self.children
+                .entry(*parent)
+                .or_insert_with(u32hashset_new)
+                .insert(tx.uid);
+        }
+        for prevout in tx.prevouts.iter().flatten() {
+            self.spends
+                .entry(prevout.txid.clone())
+                .or_insert_with(|| u32hashmap_with_capacity(1))
+                .entry(prevout.vout)
+                .or_default()
+                .push(tx.uid);
+        }
+        self.transactions.insert(uid, tx);
+        if !missing_parents.is_empty() {
+            self.missing_parents.insert(uid, missing_parents);
+        }
+
+        // this may be the missing parent of some of its children
+        let children: Vec<u32> = self.children(uid).collect();
+        for child in children {
+            if let Some(parents) = self.missing_parents.get_mut(&child) {
+                parents.retain(|parent| *parent != uid);
+                if parents.is_empty() {
+                    self.missing_parents.remove(&child);
+                }
+            }
+        }
+
+        if orphaned {
+            self.orphans.insert(uid);
+            self.orphans.extend(self.descendants(uid));
+        } else if !self.orphans.is_empty() {
+            self.refresh_descendant_orphans(uid);
+        }
+    }
+
+    // re-check the orphaned descendants of a transaction which is not an orphan itself,
+    // without walking every other orphan in the mempool
+    fn refresh_descendant_orphans(&mut self, uid: u32) {
+        let orphaned: Vec<u32> = self
+            .descendants(uid)
+            .into_iter()
+            .filter(|descendant| self.orphans.contains(descendant))
+            .collect();
+        for descendant in orphaned {
+            let missing_parent = self.missing_parents.contains_key(&descendant)
+                || self
+                    .ancestors(descendant)
+                    .iter()
+                    .any(|ancestor| self.missing_parents.contains_key(ancestor));
+            if !missing_parent {
+                self.orphans.remove(&descendant);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, uid: u32) -> Option<ThreadTransaction> {
+        let tx = self.transactions.remove(&uid)?;
+        self.orphans.remove(&uid);
+        self.missing_parents.remove(&uid);
+        self.unlink_parents(&tx);
+        self.unlink_prevouts(&tx);
+        Some(tx)
+    }
+
+    pub fn set_fee_delta(&mut self, uid: u32, delta: f64) {
+        if self.fee_deltas.insert(uid, delta) != Some(delta) {
+            self.changed_fee_deltas.insert(uid);
+        }
+    }
+
+    pub fn remove_fee_delta(&mut self, uid: u32) {
+        if self.fee_deltas.remove(&uid).is_some() {
+            self.changed_fee_deltas.insert(uid);
+        }
+    }
+
+    /// Replaces every fee delta, only marking the ones which actually changed
+    pub fn set_fee_deltas(&mut self, accelerations: &[ThreadAcceleration]) {
+        let fee_deltas = fee_deltas(accelerations);
+        let removed: Vec<u32> = self
+            .fee_deltas
+            .keys()
+            .copied()
+            .filter(|uid| !fee_deltas.contains_key(uid))
+            .collect();
+        for uid in removed {
+            self.remove_fee_delta(uid);
+        }
+        for (uid, delta) in fee_deltas {
+            self.set_fee_delta(uid, delta);
+        }
+    }
+
+    /// Takes the in-mempool transactions whose fee delta changed since the last call,
+    /// along with all of their ancestors and descendants, since their rates may change too.
+    pub fn take_dirty(&mut self) -> HashSet<u32, U32HasherState> {
+        let mut dirty = u32hashset_new();
+        for uid in std::mem::replace(&mut self.changed_fee_deltas, u32hashset_new()) {
+            // a uid already dirtied as a relative of another delta can still have relatives
+            // of its own outside that one's ancestors and descendants
+            if self.contains(uid) {
+                dirty.insert(uid);
+                dirty.extend(self.ancestors(uid));
+                dirty.extend(self.descendants(uid));
+            }
+        }
+        dirty
+    }
+
+    fn unlink_prevouts(&mut self, tx: &ThreadTransaction) {
+        for prevout in tx.prevouts.iter().flatten() {
+            if let Some(outputs) = self.spends.get_mut(&prevout.txid) {
+                if let Some(spenders) = outputs.get_mut(&prevout.vout) {
+                    spenders.retain(|spender| *spender != tx.uid);
+                    if spenders.is_empty() {
+                        outputs.remove(&prevout.vout);
+                    }
+                }
+                if outputs.is_empty() {
+                    self.spends.remove(&prevout.txid);
+                }
+            }
+        }
+    }
+
+    /// Removes these transactions, and removes or orphans their descendants depending on `mode`.
+    ///
+    /// Returns the descendants which were removed or orphaned, excluding any of `uids`.
+    pub fn remove_all(&mut self, uids: &[u32], mode: RemovalMode) -> Vec<u32> {
+        let mut cascaded: HashSet<u32, U32HasherState> = u32hashset_new();
+        if mode != RemovalMode::Keep {
+            for uid in uids {
+                if self.contains(*uid) {
+                    cascaded.extend(self.descendants(*uid));
+                }
+            }
+            for uid in uids {
+                cascaded.remove(uid);
+            }
+        }
+        if mode == RemovalMode::Orphan {
+            // the children of each removed transaction wait for that parent in particular
+            for uid in uids {
+                // a parent which is already gone is not missing because of this removal
+                if !self.contains(*uid) {
+                    continue;
+                }
+                let children: Vec<u32> = self.children(*uid).collect();
+                for child in children {
+                    self.missing_parents.entry(child).or_default().push(*uid);
+                }
+            }
+        }
+        for uid in uids {
+            self.remove(*uid);
+        }
+        match mode {
+            RemovalMode::Keep => {}
+            RemovalMode::Cascade => {
+                for uid in &cascaded {
+                    self.remove(*uid);
+                }
+            }
+            RemovalMode::Orphan => self.orphans.extend(cascaded.iter().copied()),
+        }
+        let mut cascaded: Vec<u32> = cascaded.into_iter().collect();
+        cascaded.sort_unstable();
+        cascaded
+    }
+
+    // re-check which orphans are still missing a parent, and re-flag their descendants
+    fn refresh_orphans(&mut self) {
+        let transactions = &self.transactions;
+        self.missing_parents.retain(|uid, parents| {
+            parents.retain(|parent| !transactions.contains_key(parent));
+            !parents.is_empty() && transactions.contains_key(uid)
+        });
+        let roots: Vec<u32> = self.missing_parents.keys().copied().collect();
+        self.orphans.clear();
+        for root in roots {
+            let descendants = self.descendants(root);
+            self.orphans.insert(root);
+            self.orphans.extend(descendants);
+        }
+    }
+
+    /// Removes the transactions confirmed in a new block, and evicts every mempool transaction
+    /// which double-spends the block, along with all of their descendants.
+    ///
+    /// `spent` lists the outpoints spent by block transactions which are not in the mempool,
+    /// or whose `prevouts` are not being tracked.
+    pub fn block_connected(
+        &mut self,
+        mined: &[u32],
+        mut spent: Vec<ThreadOutpoint>,
+    ) -> Vec<Eviction> {
+        let mut evictions: Vec<Eviction> = Vec::new();
+        for uid in mined {
+            let removed = self.remove(*uid);
+            // children of a mined transaction now spend confirmed outputs,
+            // even if it was a missing parent
+            for child in self.children.remove(uid).into_iter().flatten() {
+                if let Some(child_tx) = self.transactions.get_mut(&child) {
+                    child_tx.inputs.retain(|input| input != uid);
+                }
+                if let Some(parents) = self.missing_parents.get_mut(&child) {
+                    parents.retain(|parent| parent != uid);
+                }
+            }
+            if let Some(tx) = removed {
+                spent.extend(tx.prevouts.into_iter().flatten());
+                self.fee_deltas.remove(uid);
+                evictions.push(Eviction {
+                    uid: *uid,
+                    reason: EvictionReason::Mined,
+                });
+            }
+        }
+
+        for outpoint in &spent {
+            let conflicts: Vec<u32> = self
+                .spends
+                .get(&outpoint.txid)
+                .and_then(|outputs| outputs.get(&outpoint.vout))
+                .cloned()
+                .unwrap_or_default();
+            for conflict in conflicts {
+                let mut descendants: Vec<u32> = self.descendants(conflict).into_iter().collect();
+                descendants.sort_unstable();
+                if self.remove(conflict).is_some() {
+                    evictions.push(Eviction {
+                        uid: conflict,
+                        reason: EvictionReason::Conflict,
+                    });
+                }
+                for descendant in descendants {
+                    if self.remove(descendant).is_some() {
+                        evictions.push(Eviction {
+                            uid: descendant,
+                            reason: EvictionReason::ConflictDescendant,
+                        });
+                    }
+                }
+            }
+        }
+        if !self.orphans.is_empty() {
+            self.refresh_orphans();
+        }
+        evictions
+    }
+
+    /// Re-adds the transactions from a block disconnected by a reorg.
+    ///
+    /// Mempool transactions which spend their outputs are re-linked as their children,
+    /// if the re-added transactions have a `txid` and the children were given with `prevouts`.
+    /// Returns any outpoints which are now double-spent, like `conflicts`.
+    pub fn block_disconnected(
+        &mut self,
+        transactions: Vec<ThreadTransaction>,
+    ) -> Option<Vec<SpendConflict>> {
+        let uids: Vec<u32> = transactions.iter().map(|tx| tx.uid).collect();
+        for tx in transactions {
+            let spenders: Vec<u32> = tx
+                .txid
+                .as_ref()
+                .and_then(|txid| self.spends.get(txid))
+                .map(|outputs| outputs.values().flatten().copied().collect())
+                .unwrap_or_default();
+            for spender in spenders {
+                if let Some(spender_tx) = self.transactions.get_mut(&spender) {
+                    if !spender_tx.inputs.contains(&tx.uid) {
+                        spender_tx.inputs.push(tx.uid);
+                        self.children
+                            .entry(tx.uid)
+                            .or_insert_with(u32hashset_new)
+                            .insert(spender);
+                    }
+                }
+            }
+            self.insert(tx);
+        }
+        self.conflicts(&uids)
+    }
+
+    /// Finds the outpoints spent by any of these transactions which are also spent by another
+    /// mempool transaction. Returns `None` if none of them provided their `prevouts`.
+    pub fn conflicts(&self, uids: &[u32]) -> Option<Vec<SpendConflict>> {
+        let mut tracked = false;
+        let mut conflicts: Vec<SpendConflict> = Vec::new();
+        for tx in uids.iter().filter_map(|uid| self.get(*uid)) {
+            for prevout in tx.prevouts.iter().flatten() {
+                tracked = true;
+                let spenders = self
+                    .spends
+                    .get(&prevout.txid)
+                    .and_then(|outputs| outputs.get(&prevout.vout));
+                if let Some(spenders) = spenders.filter(|spenders| spenders.len() > 1) {
+                    let reported = conflicts.iter().any(|conflict| {
+                        conflict.vout == prevout.vout && conflict.txid == prevout.txid
+                    });
+                    if !reported {
+                        conflicts.push(SpendConflict {
+                            txid: prevout.txid.clone(),
+                            vout: prevout.vout,
+                            uids: spenders.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        tracked.then_some(conflicts)
+    }
+
+    fn unlink_parents(&mut self, tx: &ThreadTransaction) {
+        for parent in &tx.inputs {
+            if let Some(siblings) = self.children.get_mut(parent) {
+                siblings.remove(&tx.uid);
+                if siblings.is_empty() {
+                    self.children.remove(parent);
+                }
+            }
+        }
+    }
+
+    /// The cluster id of a transaction as of the last `refresh_cluster_ids`,
+    /// or `None` if it had no in-mempool relatives
+    #[inline]
+    pub fn cluster_id(&self, uid: u32) -> Option<u32> {
+        self.cluster_ids.get(&uid).copied()
+    }
+
+    /// Tuples of uid and cluster id for every clustered transaction, ordered by uid
+    pub fn cluster_ids(&self) -> Vec<Vec<u32>> {
+        let mut cluster_ids: Vec<Vec<u32>> = self
+            .cluster_ids
+            .iter()
+            .map(|(uid, id)| vec![*uid, *id])
+            .collect();
+        cluster_ids.sort_unstable();
+        cluster_ids
+    }
+
+    /// Assigns a cluster id to every connected component of two or more transactions.
+    ///
+    /// A component keeps the id of a previous cluster if it still contains all of that
+    /// cluster's members, so ids survive as long as a cluster is unchanged or only grows.
+    /// When clusters merge, the id of the largest one survives, or the oldest if they are
+    /// the same size. Any other component gets a new id.
+    pub fn refresh_cluster_ids(&mut self) {
+        let mut cluster_ids = u32hashmap_with_capacity(self.cluster_ids.len());
+        let mut cluster_sizes = u32hashmap_with_capacity(self.cluster_sizes.len());
+        let mut visited: HashSet<u32, U32HasherState> = u32hashset_new();
+        for uid in self.transactions.keys() {
+            if visited.contains(uid)
+                || (self.parents(*uid).next().is_none() && self.children(*uid).next().is_none())
+            {
+                continue;
+            }
+            let cluster = self.cluster(*uid);
+            visited.extend(cluster.iter().copied());
+
+            // how many members of each previous cluster are still in this one
+            let mut previous: HashMap<u32, usize, U32HasherState> = u32hashmap_with_capacity(1);
+            for member in &cluster {
+                if let Some(id) = self.cluster_ids.get(member) {
+                    *previous.entry(*id).or_default() += 1;
+                }
+            }
+            let id = previous
+                .into_iter()
+                .filter(|(id, count)| self.cluster_sizes.get(id) == Some(count))
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .map_or_else(
+                    || {
+                        self.next_cluster_id += 1;
+                        self.next_cluster_id - 1
+                    },
+                    |(id, _)| id,
+                );
+            cluster_sizes.insert(id, cluster.len());
+            cluster_ids.extend(cluster.into_iter().map(|member| (member, id)));
+        }
+        self.cluster_ids = cluster_ids;
+        self.cluster_sizes = cluster_sizes;
+    }
+
+    /// Copies some of the transactions into a standalone map, for running gbt over part
+    /// of the mempool. Also returns the largest uid in the map.
+    pub fn subset<I>(&self, uids: I) -> (ThreadTransactionsMap, usize)
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        let uids = uids.into_iter();
+        let mut max_uid: u32 = 0;
+        let mut subset: ThreadTransactionsMap = u32hashmap_with_capacity(uids.size_hint().0);
+        for uid in uids {
+            if let Some(tx) = self.get(uid) {
+                max_uid = max_uid.max(uid);
+                subset.insert(uid, tx.clone());
+            }
+        }
+        (subset, max_uid as usize)
+    }
+
+    /// Iterates over the in-mempool parents of a transaction
+    pub fn parents(&self, uid: u32) -> impl Iterator<Item = u32> + '_ {
+        self.transactions
+            .get(&uid)
+            .into_iter()
+            .flat_map(|tx| tx.inputs.iter().copied())
+            .filter(|parent| self.transactions.contains_key(parent))
+    }
+
+    /// Iterates over the in-mempool children of a transaction
+    pub fn children(&self, uid: u32) -> impl Iterator<Item = u32> + '_ {
+        self.children.get(&uid).into_iter().flatten().copied()
+    }
+
+    /// All in-mempool ancestors of a transaction, not including the transaction itself
+    pub fn ancestors(&self, uid: u32) -> HashSet<u32, U32HasherState> {
+        self.walk(uid, |mempool, next, stack| {
+            stack.extend(mempool.parents(next));
+        })
+    }
+
+    /// All in-mempool descendants of a transaction, not including the transaction itself
+    pub fn descendants(&self, uid: u32) -> HashSet<u32, U32HasherState> {
+        self.walk(uid, |mempool, next, stack| {
+            stack.extend(mempool.children(next));
+        })
+    }
+
+    /// Every transaction connected to this one by any chain of in-mempool spends,
+    /// including the transaction itself
+    pub fn cluster(&self, uid: u32) -> HashSet<u32, U32HasherState> {
+        let mut cluster = self.walk(uid, |mempool, next, stack| {
+            stack.extend(mempool.parents(next));
+            stack.extend(mempool.children(next));
+        });
+        if self.contains(uid) {
+            cluster.insert(uid);
+        }
+        cluster
+    }
+
+    // iterative depth-first traversal, to avoid recursing over long chains
+    fn walk<F>(&self, root: u32, mut expand: F) -> HashSet<u32, U32HasherState>
+    where
+        F: FnMut(&Self, u32, &mut Vec<u32>),
+    {
+        let mut visited: HashSet<u32, U32HasherState> = u32hashset_new();
+        let mut stack: Vec<u32> = Vec::new();
+        expand(self, root, &mut stack);
+        while let Some(next) = stack.pop() {
+            if next != root && visited.insert(next) {
+                expand(self, next, &mut stack);
+            }
+        }
+        visited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EvictionReason, Mempool, RemovalMode};
+    use crate::{
+        thread_acceleration::ThreadAcceleration,
+        thread_outpoint::ThreadOutpoint,
+        thread_transaction::{test_tx, ThreadTransaction},
+    };
+
+    fn outpoint(uid: u32, vout: u32) -> ThreadOutpoint {
+        ThreadOutpoint {
+            txid: format!("{uid:064x}"),
+            vout,
+        }
+    }
+
+    #[test]
+    fn test_block_connected_and_disconnected() {
+        let mut mempool = Mempool::with_capacity(8);
+        // tx 9 is confirmed
+        for (uid, inputs, prevout) in [
+            (0, vec![], outpoint(9, 0)),
+            (1, vec![0], outpoint(0, 0)),
+            (2, vec![], outpoint(9, 1)),
+            (3, vec![2], outpoint(2, 0)),
+        ] {
+            mempool.insert(ThreadTransaction {
+                prevouts: Some(vec![prevout]),
+                ..test_tx(uid, 1000.0, 400, inputs)
+            });
+        }
+        assert_eq!(mempool.conflicts(&[0, 1, 2, 3]).map(|c| c.len()), Some(0));
+
+        // a block mines tx 0, and double-spends tx 2
+        let evictions: Vec<(u32, EvictionReason)> = mempool
+            .block_connected(&[0], vec![outpoint(9, 1)])
+            .into_iter()
+            .map(|eviction| (eviction.uid, eviction.reason))
+            .collect();
+        assert_eq!(
+            evictions,
+            vec![
+                (0, EvictionReason::Mined),
+                (2, EvictionReason::Conflict),
+                (3, EvictionReason::ConflictDescendant),
+            ]
+        );
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.get(1).is_some_and(|tx| tx.inputs.is_empty()));
+
+        // reorging the block out re-links tx 1 to its parent
+        let conflicts = mempool.block_disconnected(vec![ThreadTransaction {
+            prevouts: Some(vec![outpoint(9, 0)]),
+            ..test_tx(0, 1000.0, 400, vec![])
+        }]);
+        assert_eq!(conflicts.map(|c| c.len()), Some(0));
+        assert_eq!(mempool.get(1).map(|tx| tx.inputs.clone()), Some(vec![0]));
+        assert_eq!(mempool.children(0).collect::<Vec<u32>>(), vec![1]);
+
+        // a second spend of the same outpoint is reported as a conflict
+        mempool.insert(ThreadTransaction {
+            prevouts: Some(vec![outpoint(9, 0)]),
+            ..test_tx(4, 1000.0, 400, vec![])
+        });
+        let conflicts = mempool.conflicts(&[4]).expect("prevouts are tracked");
+        assert_eq!(conflicts[0].uids, vec![0, 4]);
+    }
+
+    #[test]
+    fn test_removal_modes() {
+        let chain = || {
+            let mut mempool = Mempool::with_capacity(4);
+            mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+            mempool.insert(test_tx(1, 1000.0, 400, vec![0]));
+            mempool.insert(test_tx(2, 1000.0, 400, vec![1]));
+            mempool.insert(test_tx(3, 1000.0, 400, vec![]));
+            mempool
+        };
+
+        let mut mempool = chain();
+        assert!(mempool.remove_all(&[0], RemovalMode::Keep).is_empty());
+        assert_eq!(mempool.len(), 3);
+        assert!(mempool.orphans.is_empty());
+
+        let mut mempool = chain();
+        assert_eq!(mempool.remove_all(&[0], RemovalMode::Cascade), vec![1, 2]);
+        assert_eq!(mempool.len(), 1);
+
+        let mut mempool = chain();
+        assert_eq!(mempool.remove_all(&[0], RemovalMode::Orphan), vec![1, 2]);
+        assert_eq!(mempool.len(), 3);
+        let mut orphans: Vec<u32> = mempool.orphans.iter().copied().collect();
+        orphans.sort_unstable();
+        assert_eq!(orphans, vec![1, 2]);
+
+        // children of orphans are orphans too, until the missing parent comes back
+        mempool.insert(test_tx(4, 1000.0, 400, vec![2]));
+        assert!(mempool.orphans.contains(&4));
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        assert!(mempool.orphans.is_empty());
+    }
+
+    #[test]
+    fn test_missing_parents() {
+        let mut mempool = Mempool::with_capacity(3);
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(1, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(2, 1000.0, 400, vec![0, 1]));
+
+        // only the parent removed with the orphan mode is missing
+        mempool.remove_all(&[0], RemovalMode::Keep);
+        mempool.remove_all(&[1], RemovalMode::Orphan);
+        assert!(mempool.orphans.contains(&2));
+        mempool.insert(test_tx(1, 1000.0, 400, vec![]));
+        assert!(mempool.orphans.is_empty());
+
+        // or until the missing parent is mined
+        mempool.remove_all(&[1], RemovalMode::Orphan);
+        assert!(mempool.orphans.contains(&2));
+        mempool.block_connected(&[1], vec![]);
+        assert!(mempool.orphans.is_empty());
+        assert!(mempool.get(2).is_some_and(|tx| tx.inputs == vec![0]));
+
+        // a parent which is already gone cannot go missing again
+        mempool.remove_all(&[0], RemovalMode::Orphan);
+        assert!(mempool.orphans.is_empty());
+        assert!(mempool.missing_parents.is_empty());
+    }
+
+    #[test]
+    fn test_missing_parent_returns() {
+        let mut mempool = Mempool::with_capacity(5);
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(1, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(2, 1000.0, 400, vec![0]));
+        mempool.insert(test_tx(3, 1000.0, 400, vec![2, 1]));
+        mempool.insert(test_tx(4, 1000.0, 400, vec![3]));
+
+        mempool.remove_all(&[0, 1], RemovalMode::Orphan);
+        let sorted_orphans = |mempool: &Mempool| {
+            let mut orphans: Vec<u32> = mempool.orphans.iter().copied().collect();
+            orphans.sort_unstable();
+            orphans
+        };
+        assert_eq!(sorted_orphans(&mempool), vec![2, 3, 4]);
+
+        // 3 and 4 still wait for 1
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        assert_eq!(sorted_orphans(&mempool), vec![3, 4]);
+        mempool.insert(test_tx(1, 1000.0, 400, vec![]));
+        assert!(mempool.orphans.is_empty());
+        assert!(mempool.missing_parents.is_empty());
+    }
+
+    #[test]
+    fn test_fee_deltas() {
+        let sorted = |uids: &mut dyn Iterator<Item = u32>| {
+            let mut uids: Vec<u32> = uids.collect();
+            uids.sort_unstable();
+            uids
+        };
+        let mut mempool = Mempool::with_capacity(4);
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(1, 1000.0, 400, vec![0]));
+        mempool.insert(test_tx(2, 1000.0, 400, vec![]));
+
+        // a delta for a transaction which has not arrived yet stays pending
+        mempool.set_fee_deltas(&[
+            ThreadAcceleration {
+                uid: 1,
+                delta: 500.0,
+            },
+            ThreadAcceleration {
+                uid: 3,
+                delta: 100.0,
+            },
+        ]);
+        assert_eq!(sorted(&mut mempool.take_dirty().into_iter()), vec![0, 1]);
+        assert!(mempool.take_dirty().is_empty());
+
+        // setting the same deltas again changes nothing
+        mempool.set_fee_delta(1, 500.0);
+        assert!(mempool.take_dirty().is_empty());
+
+        mempool.insert(test_tx(3, 1000.0, 400, vec![2]));
+        assert_eq!(sorted(&mut mempool.take_dirty().into_iter()), vec![2, 3]);
+
+        mempool.set_fee_deltas(&[ThreadAcceleration {
+            uid: 3,
+            delta: 100.0,
+        }]);
+        assert_eq!(sorted(&mut mempool.take_dirty().into_iter()), vec![0, 1]);
+        assert_eq!(sorted(&mut mempool.fee_deltas.keys().copied()), vec![3]);
+
+        mempool.block_connected(&[3], vec![]);
+        assert!(mempool.fee_deltas.is_empty());
+
+        // deltas on two relatives each dirty the relatives the other one lacks
+        for (uid, inputs) in [
+            (4, vec![]),
+            (5, vec![4]),
+            (7, vec![]),
+            (6, vec![5, 7]),
+            (8, vec![5]),
+        ] {
+            mempool.insert(test_tx(uid, 1000.0, 400, inputs));
+        }
+        mempool.set_fee_deltas(&[
+            ThreadAcceleration {
+                uid: 5,
+                delta: 100.0,
+            },
+            ThreadAcceleration {
+                uid: 6,
+                delta: 100.0,
+            },
+        ]);
+        assert_eq!(
+            sorted(&mut mempool.take_dirty().into_iter()),
+            vec![4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn test_cluster_ids() {
+        let mut mempool = Mempool::with_capacity(6);
+        mempool.insert(test_tx(0, 1000.0, 400, vec![]));
+        mempool.insert(test_tx(1, 1000.0, 400, vec![0]));
+        mempool.insert(test_tx(2, 1000.0, 400, vec![]));
+        mempool.refresh_cluster_ids();
+        let first = mempool.cluster_id(0).expect("clustered");
+        assert_eq!(mempool.cluster_ids(), vec![vec![0, first], vec![1, first]]);
+        assert_eq!(mempool.cluster_id(2), None);
+
+        // growing clusters keep their id
+        mempool.insert(test_tx(3, 1000.0, 400, vec![1]));
+        mempool.insert(test_tx(4, 1000.0, 400, vec![2]));
+        mempool.refresh_cluster_ids();
+        assert_eq!(mempool.cluster_id(3), Some(first));
+        let second = mempool.cluster_id(2).expect("clustered");
+        assert_ne!(first, second);
+
+        // merged clusters keep the id of the largest
+        mempool.insert(test_tx(5, 1000.0, 400, vec![3, 4]));
+        mempool.refresh_cluster_ids();
+        assert!((0..6).all(|uid| mempool.cluster_id(uid) == Some(first)));
+
+        // losing a member splits the cluster, and neither half keeps its id
+        mempool.remove(1);
+        mempool.refresh_cluster_ids();
+        assert_eq!(mempool.cluster_id(0), None);
+        let third = mempool.cluster_id(5).expect("clustered");
+        assert!(third != first && third != second);
+        assert!([2, 3, 4]
+            .iter()
+            .all(|uid| mempool.cluster_id(*uid) == Some(third)));
+    }
+}
@@ -0,0 +1,83 @@
+use napi_derive::napi;
+use std::collections::HashSet;
+
+use crate::{
+    mempool::Mempool,
+    thread_transaction::ThreadTransaction,
+    u32_hasher_types::{u32hashset_new, U32HasherState},
+};
+
+/// Transaction selection rules for modelling a miner with a non-standard policy.
+///
+/// Flags are bitmasks over `ThreadTransaction.flags`, like the backend's `TransactionFlags`.
+/// Including transactions for out-of-band payments is out of scope for a policy, which only
+/// filters by flags: pass those payments as fee deltas in `accelerations` instead.
+#[derive(Clone, Debug, Default)]
+#[napi(object)]
+pub struct PolicyFilter {
+    /// Leave out transactions with any of these flags, along with all of their descendants
+    pub exclude_flags: Option<f64>,
+    /// Keep transactions with any of these flags, even if they match `exclude_flags`
+    pub include_flags: Option<f64>,
+}
+
+impl PolicyFilter {
+    fn rejects(&self, tx: &ThreadTransaction) -> bool {
+        let flags = tx.flags.map_or(0, |flags| flags as u64);
+        let exclude = self.exclude_flags.map_or(0, |flags| flags as u64);
+        let include = self.include_flags.map_or(0, |flags| flags as u64);
+        flags & exclude != 0 && flags & include == 0
+    }
+
+    /// Every mempool transaction rejected by this policy, along with all of their descendants
+    pub fn excluded(&self, mempool: &Mempool) -> HashSet<u32, U32HasherState> {
+        let mut excluded = u32hashset_new();
+        for tx in mempool.transactions.values() {
+            if self.rejects(tx) && excluded.insert(tx.uid) {
+                excluded.extend(mempool.descendants(tx.uid));
+            }
+        }
+        excluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PolicyFilter;
+    use crate::{
+        mempool::Mempool,
+        thread_transaction::{test_tx, ThreadTransaction},
+    };
+
+    #[test]
+    fn test_excluded() {
+        let mut mempool = Mempool::with_capacity(4);
+        for (uid, flags, inputs) in [
+            (0, 1.0, vec![]),
+            (1, 0.0, vec![0]),
+            (2, 3.0, vec![]),
+            (3, 4.0, vec![]),
+        ] {
+            mempool.insert(ThreadTransaction {
+                flags: Some(flags),
+                ..test_tx(uid, 1000.0, 400, inputs)
+            });
+        }
+
+        let policy = PolicyFilter {
+            exclude_flags: Some(1.0),
+            include_flags: None,
+        };
+        let mut excluded: Vec<u32> = policy.excluded(&mempool).into_iter().collect();
+        excluded.sort_unstable();
+        assert_eq!(excluded, vec![0, 1, 2]);
+
+        let policy = PolicyFilter {
+            exclude_flags: Some(1.0),
+            include_flags: Some(2.0),
+        };
+        let mut excluded: Vec<u32> = policy.excluded(&mempool).into_iter().collect();
+        excluded.sort_unstable();
+        assert_eq!(excluded, vec![0, 1]);
+    }
+}
@@ -0,0 +1,421 @@
+use napi_derive::napi;
+use std::collections::HashSet;
+use tracing::{debug, info};
+
+use crate::{
+    feerate_diagram::{dominance, FeerateDiagram},
+    gbt::{self, GbtContext},
+    mempool::Mempool,
+    thread_acceleration::FeeDeltas,
+    thread_transaction::ThreadTransaction,
+    u32_hasher_types::{u32hashmap_with_capacity, u32hashset_new, U32HasherState},
+    GbtOptions, GbtResult, ThreadTransactionsMap,
+};
+
+/// BIP125 rule 5: the maximum number of transactions a replacement may evict
+const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+/// BIP125 rule 4: the minimum feerate a replacement must pay for its own relay, in sats/vB
+pub const DEFAULT_INCREMENTAL_RELAY_FEE_RATE: f64 = 1.0;
+
+/// Why a replacement package would be rejected.
+#[derive(Debug, PartialEq, Eq)]
+#[napi(string_enum = "kebab-case")]
+pub enum RbfRejection {
+    /// The package spends an output of a transaction it would replace
+    SpendsConflictingTx,
+    /// BIP125 rule 5: the package would evict too many transactions
+    TooManyReplacements,
+    /// BIP125 rule 3: the package pays less absolute fee than the transactions it evicts
+    InsufficientFee,
+    /// BIP125 rule 4: the package does not pay for its own relay at the incremental relay feerate
+    InsufficientFeeBump,
+    /// The feerate diagram of the affected clusters would not strictly improve
+    WorseFeerateDiagram,
+}
+
+/// The projected block of a transaction, or null if it was not in any projected block.
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct TxPosition {
+    pub uid: u32,
+    pub block: Option<u32>,
+}
+
+/// The result of evaluating a replacement package against the mempool.
+///
+///             accepted: whether the package would replace its conflicts
+///               reason: why the package would be rejected, if it would be
+///             replaced: uids of the conflicts and all of their descendants
+///         replaced_fee: total fee of the replaced transactions, including fee deltas
+///      replacement_fee: total fee of the package. Fee deltas are keyed by mempool uids,
+///                       so none apply to the package.
+///    replacement_vsize: total sigop-adjusted vsize of the package
+///     positions_before: projected blocks of the replaced transactions in the current mempool
+///      positions_after: projected blocks of the package transactions after replacement
+#[napi(object)]
+pub struct RbfEvaluation {
+    pub accepted: bool,
+    pub reason: Option<RbfRejection>,
+    pub replaced: Vec<u32>,
+    pub replaced_fee: f64,
+    pub replacement_fee: f64,
+    pub replacement_vsize: f64,
+    pub positions_before: Vec<TxPosition>,
+    pub positions_after: Vec<TxPosition>,
+}
+
+/// Evaluate a replacement package against the mempool, using the BIP125 rules 3-5
+/// and the feerate diagram check from package RBF.
+///
+/// `package` transactions are given fresh uids internally, so any uids can identify them,
+/// and inputs which match a package uid spend that package transaction rather than the
+/// mempool one. `conflicts` are the uids of the mempool transactions the package double-spends.
+///
+/// # Errors
+///
+/// Fails if there are not enough uids left above the mempool's to number the package.
+pub fn evaluate_replacement(
+    mempool: &Mempool,
+    package: Vec<ThreadTransaction>,
+    conflicts: &[u32],
+    fee_deltas: &FeeDeltas,
+    max_block_weight: u32,
+    max_blocks: usize,
+) -> Result<RbfEvaluation, String> {
+    let first_uid = first_package_uid(mempool, package.len())
+        .ok_or("no uids left above the mempool's for the replacement package")?;
+    // deltas from there up are pending for transactions which have not arrived yet,
+    // and must not apply to the package
+    let mut mempool_deltas = fee_deltas.clone();
+    mempool_deltas.retain(|uid, _| *uid < first_uid);
+    let fee_deltas = &mempool_deltas;
+    let modified_fee =
+        |tx: &ThreadTransaction| tx.fee + fee_deltas.get(&tx.uid).copied().unwrap_or(0.0);
+
+    let mut package_uids = u32hashmap_with_capacity(package.len());
+    for (index, tx) in package.iter().enumerate() {
+        package_uids.insert(tx.uid, first_uid + index as u32);
+    }
+    let caller_uids: Vec<u32> = package.iter().map(|tx| tx.uid).collect();
+    let package: Vec<ThreadTransaction> = package
+        .into_iter()
+        .enumerate()
+        .map(|(index, tx)| ThreadTransaction {
+            uid: first_uid + index as u32,
+            inputs: tx
+                .inputs
+                .iter()
+                .map(|input| package_uids.get(input).copied().unwrap_or(*input))
+                .collect(),
+            ..tx
+        })
+        .collect();
+
+    let mut replaced: HashSet<u32, U32HasherState> = u32hashset_new();
+    for conflict in conflicts {
+        if mempool.contains(*conflict) {
+            replaced.insert(*conflict);
+            replaced.extend(mempool.descendants(*conflict));
+        }
+    }
+    let replaced_fee: f64 = replaced
+        .iter()
+        .filter_map(|uid| mempool.get(*uid))
+        .map(modified_fee)
+        .sum();
+    let replacement_fee: f64 = package.iter().map(|tx| tx.fee).sum();
+    let replacement_vsize: u32 = package
+        .iter()
+        .map(ThreadTransaction::sigop_adjusted_vsize)
+        .sum();
+    info!(
+        "Evaluating replacement of {} transactions by a package of {}",
+        replaced.len(),
+        package.len()
+    );
+
+    let reason = if package
+        .iter()
+        .any(|tx| tx.inputs.iter().any(|input| replaced.contains(input)))
+    {
+        Some(RbfRejection::SpendsConflictingTx)
+    } else if replaced.len() > MAX_REPLACEMENT_CANDIDATES {
+        Some(RbfRejection::TooManyReplacements)
+    } else if replacement_fee < replaced_fee {
+        Some(RbfRejection::InsufficientFee)
+    } else if replacement_fee - replaced_fee
+        < DEFAULT_INCREMENTAL_RELAY_FEE_RATE * f64::from(replacement_vsize)
+    {
+        Some(RbfRejection::InsufficientFeeBump)
+    } else if !improves_feerate_diagram(mempool, &package, &replaced, fee_deltas) {
+        Some(RbfRejection::WorseFeerateDiagram)
+    } else {
+        None
+    };
+    debug!("Replacement rejection reason: {:?}", reason);
+
+    // project the whole mempool with and without the replacement
+    let options = GbtOptions::default();
+    let before_result = gbt::project(mempool, fee_deltas, max_block_weight, max_blocks, &options);
+    let mut after: ThreadTransactionsMap = mempool.transactions.clone();
+    after.retain(|uid, _| !replaced.contains(uid));
+    let new_uids: Vec<u32> = package.iter().map(|tx| tx.uid).collect();
+    after.extend(package.into_iter().map(|tx| (tx.uid, tx)));
+    let after_result = gbt::project_transactions(
+        mempool,
+        after,
+        fee_deltas,
+        max_block_weight,
+        max_blocks,
+        &options,
+    );
+    // report the package by the caller's uids
+    let positions_after = block_positions(&after_result, &new_uids)
+        .into_iter()
+        .zip(caller_uids)
+        .map(|(position, uid)| TxPosition { uid, ..position })
+        .collect();
+
+    let mut replaced: Vec<u32> = replaced.into_iter().collect();
+    replaced.sort_unstable();
+
+    Ok(RbfEvaluation {
+        accepted: reason.is_none(),
+        reason,
+        positions_before: block_positions(&before_result, &replaced),
+        positions_after,
+        replaced,
+        replaced_fee,
+        replacement_fee,
+        replacement_vsize: f64::from(replacement_vsize),
+    })
+}
+
+// Package transactions are numbered from above every mempool uid, so they can't overwrite
+// mempool transactions, as long as the last one still fits in a u32
+fn first_package_uid(mempool: &Mempool, package_len: usize) -> Option<u32> {
+    let first_uid = mempool
+        .transactions
+        .keys()
+        .max()
+        .map_or(Some(0), |uid| uid.checked_add(1))?;
+    u32::try_from(package_len.saturating_sub(1))
+        .ok()
+        .and_then(|last| first_uid.checked_add(last))
+        .map(|_| first_uid)
+}
+
+// The replacement must make the combined feerate diagram of every affected cluster
+// at least as good everywhere, and strictly better somewhere.
+fn improves_feerate_diagram(
+    mempool: &Mempool,
+    package: &[ThreadTransaction],
+    replaced: &HashSet<u32, U32HasherState>,
+    fee_deltas: &FeeDeltas,
+) -> bool {
+    let mut affected: HashSet<u32, U32HasherState> = u32hashset_new();
+    for uid in replaced
+        .iter()
+        .chain(package.iter().flat_map(|tx| tx.inputs.iter()))
+    {
+        if mempool.contains(*uid) && !affected.contains(uid) {
+            affected.extend(mempool.cluster(*uid));
+        }
+    }
+
+    let (mut old_clusters, max_uid) = mempool.subset(affected.iter().copied());
+    let (mut new_clusters, _) = mempool.subset(
+        affected
+            .iter()
+            .copied()
+            .filter(|uid| !replaced.contains(uid)),
+    );
+    for tx in package {
+        new_clusters.insert(tx.uid, tx.clone());
+    }
+    let max_uid = package
+        .iter()
+        .map(|tx| tx.uid as usize)
+        .fold(max_uid, usize::max);
+
+    let context = GbtContext::new(fee_deltas);
+    let options = GbtOptions {
+        diagram: Some(true),
+        ..GbtOptions::default()
+    };
+    let old_diagram = FeerateDiagram::from_points(
+        gbt::gbt(
+            &mut old_clusters,
+            &context,
+            max_uid,
+            u32::MAX,
+            1,
+            &options,
+        )
+        .diagram
+        .unwrap_or_default(),
+    );
+    let new_diagram = FeerateDiagram::from_points(
+        gbt::gbt(
+            &mut new_clusters,
+            &context,
+            max_uid,
+            u32::MAX,
+            1,
+            &options,
+        )
+        .diagram
+        .unwrap_or_default(),
+    );
+    let (new_better, old_better) = dominance(&new_diagram, &old_diagram);
+    old_better.is_empty() && !new_better.is_empty()
+}
+
+/// Where each of these transactions was placed in a gbt result
+pub fn block_positions(result: &GbtResult, uids: &[u32]) -> Vec<TxPosition> {
+    let mut positions = u32hashmap_with_capacity(uids.len());
+    for uid in uids {
+        positions.insert(*uid, None);
+    }
+    for (index, block) in result.blocks.iter().enumerate() {
+        for uid in block {
+            if let Some(position) = positions.get_mut(uid) {
+                *position = Some(index as u32);
+            }
+        }
+    }
+    uids.iter()
+        .map(|uid| TxPosition {
+            uid: *uid,
+            block: positions.get(uid).copied().flatten(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate_replacement, first_package_uid, RbfRejection};
+    use crate::{
+        mempool::Mempool,
+        thread_acceleration::FeeDeltas,
+        thread_transaction::{test_tx as tx, ThreadTransaction},
+        u32_hasher_types::u32hashmap_with_capacity,
+    };
+
+    fn mempool() -> Mempool {
+        let mut mempool = Mempool::with_capacity(4);
+        mempool.insert(tx(0, 1000.0, 400, vec![]));
+        mempool.insert(tx(1, 500.0, 400, vec![0]));
+        mempool.insert(tx(2, 200.0, 400, vec![]));
+        mempool
+    }
+
+    #[test]
+    fn test_accepted_replacement() {
+        let result = evaluate_replacement(
+            &mempool(),
+            vec![tx(10, 2000.0, 400, vec![])],
+            &[0],
+            &u32hashmap_with_capacity(0),
+            4_000_000,
+            8,
+        )
+        .expect("uids are left for the package");
+        assert_eq!(result.reason, None);
+        assert!(result.accepted);
+        assert_eq!(result.replaced, vec![0, 1]);
+        assert_eq!(result.replaced_fee, 1500.0);
+        assert_eq!(result.positions_before[0].block, Some(0));
+        assert_eq!(result.positions_after[0].block, Some(0));
+    }
+
+    #[test]
+    fn test_package_uids() {
+        let mempool = mempool();
+        let no_deltas: FeeDeltas = u32hashmap_with_capacity(0);
+        // package uids which collide with the mempool, and spend each other
+        let result = evaluate_replacement(
+            &mempool,
+            vec![tx(0, 1000.0, 400, vec![]), tx(1, 2000.0, 400, vec![0])],
+            &[2],
+            &no_deltas,
+            4_000_000,
+            8,
+        )
+        .expect("uids are left for the package");
+        assert!(result.accepted);
+        assert_eq!(result.replaced, vec![2]);
+        let positions: Vec<(u32, Option<u32>)> = result
+            .positions_after
+            .iter()
+            .map(|position| (position.uid, position.block))
+            .collect();
+        assert_eq!(positions, vec![(0, Some(0)), (1, Some(0))]);
+
+        // a uid far above the mempool's
+        let result = evaluate_replacement(
+            &mempool,
+            vec![tx(u32::MAX, 2000.0, 400, vec![])],
+            &[0],
+            &no_deltas,
+            4_000_000,
+            8,
+        )
+        .expect("uids are left for the package");
+        assert!(result.accepted);
+        assert_eq!(result.positions_after[0].uid, u32::MAX);
+        assert_eq!(result.positions_after[0].block, Some(0));
+
+        // a pending delta for the uid after the mempool's does not apply to the package
+        let mut deltas: FeeDeltas = u32hashmap_with_capacity(1);
+        deltas.insert(3, 10_000.0);
+        let result = evaluate_replacement(
+            &mempool,
+            vec![tx(3, 2000.0, 400, vec![])],
+            &[0],
+            &deltas,
+            4_000_000,
+            8,
+        )
+        .expect("uids are left for the package");
+        assert_eq!(result.replacement_fee, 2000.0);
+        assert!(result.accepted);
+
+        // no uids left above the mempool's
+        let mut full = mempool;
+        full.insert(tx(u32::MAX - 1, 1000.0, 400, vec![]));
+        let package = || vec![tx(10, 2000.0, 400, vec![]), tx(11, 2000.0, 400, vec![])];
+        assert!(evaluate_replacement(&full, package(), &[0], &no_deltas, 4_000_000, 8).is_err());
+        assert_eq!(first_package_uid(&full, 1), Some(u32::MAX));
+        assert_eq!(first_package_uid(&full, 2), None);
+        assert_eq!(first_package_uid(&Mempool::with_capacity(0), 0), Some(0));
+    }
+
+    #[test]
+    fn test_rejected_replacements() {
+        let mempool = mempool();
+        let no_deltas: FeeDeltas = u32hashmap_with_capacity(0);
+        let reason = |package: Vec<ThreadTransaction>, conflicts: &[u32]| {
+            evaluate_replacement(&mempool, package, conflicts, &no_deltas, 4_000_000, 8)
+                .expect("uids are left for the package")
+                .reason
+        };
+        assert_eq!(
+            reason(vec![tx(10, 2000.0, 400, vec![1])], &[0]),
+            Some(RbfRejection::SpendsConflictingTx)
+        );
+        assert_eq!(
+            reason(vec![tx(10, 1400.0, 400, vec![])], &[0]),
+            Some(RbfRejection::InsufficientFee)
+        );
+        assert_eq!(
+            reason(vec![tx(10, 1550.0, 400, vec![])], &[0]),
+            Some(RbfRejection::InsufficientFeeBump)
+        );
+        // pays more in total, but a much lower feerate than the transaction it replaces
+        assert_eq!(
+            reason(vec![tx(10, 1300.0, 4000, vec![])], &[2]),
+            Some(RbfRejection::WorseFeerateDiagram)
+        );
+    }
+}
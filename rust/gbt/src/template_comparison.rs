@@ -0,0 +1,335 @@
+use napi_derive::napi;
+use std::collections::HashMap;
+
+use crate::{
+    feerate_diagram::{dominance, DiagramPoint, FeerateDiagram},
+    gbt::{self, GbtContext},
+    getblocktemplate::GetBlockTemplate,
+    mempool::Mempool,
+    thread_acceleration::FeeDeltas,
+    u32_hasher_types::{u32hashmap_with_capacity, u32hashset_new},
+    GbtOptions, ThreadTransactionsMap,
+};
+
+/// The result of comparing two block templates built over the same mempool.
+///
+/// `expected` is the reference template (e.g. a `GbtResult` projection),
+/// and `actual` is the template being scored against it (e.g. a mined block).
+///
+///           expected_fees / actual_fees: total fees of each template, including fee deltas
+///         expected_vsize / actual_vsize: total sigop-adjusted vsize of each template
+///                        fee_difference: `actual_fees` minus `expected_fees`
+///                                        (negative if `actual` earned less)
+///                             fee_score: `actual_fees` as a fraction of `expected_fees`
+///     expected_better / actual_better: `[start, end]` vsize ranges where each template's
+///                                        feerate diagram has more fee available than the other's
+///     expected_diagram / actual_diagram: the feerate diagram of each template
+#[napi(object)]
+pub struct TemplateComparison {
+    pub expected_fees: f64,
+    pub actual_fees: f64,
+    pub expected_vsize: f64,
+    pub actual_vsize: f64,
+    pub fee_difference: f64,
+    pub fee_score: f64,
+    pub expected_better: Vec<Vec<f64>>,
+    pub actual_better: Vec<Vec<f64>>,
+    pub expected_diagram: Vec<DiagramPoint>,
+    pub actual_diagram: Vec<DiagramPoint>,
+}
+
+/// The transactions and fees which differ between two block templates.
+///
+///                         missing: uids in `expected` which are not in `actual`
+///                           extra: uids in `actual` which are not in `expected`
+///                       reordered: the fewest uids in both templates which would need to move
+///                                  for `actual` to match the order of `expected`
+///     expected_fees / actual_fees: total fees of each template, including fee deltas
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct TemplateDiff {
+    pub missing: Vec<u32>,
+    pub extra: Vec<u32>,
+    pub reordered: Vec<u32>,
+    pub expected_fees: f64,
+    pub actual_fees: f64,
+}
+
+/// How a node's `getblocktemplate` differs from a projected block over the same mempool.
+///
+///           diff: the differences from the projected block to the node's template, with the
+///                 node's transactions matched to uids by txid. Fees exclude any fee deltas,
+///                 and the node's fees are the ones it reported.
+///        unknown: txids in the node's template which are not in the mempool, or an empty string
+///                 for entries given without a txid
+/// fee_difference: the node's total fees minus the projected block's
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct NodeTemplateDiff {
+    pub diff: TemplateDiff,
+    pub unknown: Vec<String>,
+    pub fee_difference: f64,
+}
+
+/// List the differences between two templates, in template order.
+///
+/// uids which are not in `transactions` count towards the lists, but not the fees.
+pub fn diff_templates(
+    transactions: &ThreadTransactionsMap,
+    fee_deltas: &FeeDeltas,
+    expected: &[u32],
+    actual: &[u32],
+) -> TemplateDiff {
+    let mut expected_set = u32hashset_new();
+    expected_set.extend(expected.iter().copied());
+    let mut actual_set = u32hashset_new();
+    actual_set.extend(actual.iter().copied());
+    let fees = |template: &[u32]| -> f64 {
+        template
+            .iter()
+            .filter_map(|uid| transactions.get(uid))
+            .map(|tx| tx.fee + fee_deltas.get(&tx.uid).copied().unwrap_or(0.0))
+            .sum()
+    };
+
+    TemplateDiff {
+        missing: expected
+            .iter()
+            .copied()
+            .filter(|uid| !actual_set.contains(uid))
+            .collect(),
+        extra: actual
+            .iter()
+            .copied()
+            .filter(|uid| !expected_set.contains(uid))
+            .collect(),
+        reordered: reordered(expected, actual),
+        expected_fees: fees(expected),
+        actual_fees: fees(actual),
+    }
+}
+
+/// Diff a node's `getblocktemplate` against the projected `block`.
+///
+/// Transactions are matched by the `txid` each mempool transaction was given with. The `hash`
+/// commits to the witness too, so it is never used for matching.
+pub fn diff_node_template(
+    mempool: &Mempool,
+    block: &[u32],
+    template: &GetBlockTemplate,
+) -> NodeTemplateDiff {
+    let uids: HashMap<&str, u32> = mempool
+        .transactions
+        .values()
+        .filter_map(|tx| tx.txid.as_deref().map(|txid| (txid, tx.uid)))
+        .collect();
+    let mut actual: Vec<u32> = Vec::with_capacity(template.transactions.len());
+    let mut unknown: Vec<String> = Vec::new();
+    for entry in &template.transactions {
+        let txid = entry.txid.as_deref().unwrap_or_default();
+        match uids.get(txid) {
+            Some(uid) => actual.push(*uid),
+            None => unknown.push(txid.to_string()),
+        }
+    }
+
+    let mut diff = diff_templates(
+        &mempool.transactions,
+        &u32hashmap_with_capacity(0),
+        block,
+        &actual,
+    );
+    diff.actual_fees = template.transactions.iter().map(|entry| entry.fee).sum();
+    NodeTemplateDiff {
+        fee_difference: diff.actual_fees - diff.expected_fees,
+        diff,
+        unknown,
+    }
+}
+
+// the uids in both templates outside of a longest run which is in the same order in each,
+// found by patience sorting their positions in `expected` in the order of `actual`
+fn reordered(expected: &[u32], actual: &[u32]) -> Vec<u32> {
+    let mut positions = u32hashmap_with_capacity(expected.len());
+    for (position, uid) in expected.iter().enumerate() {
+        positions.insert(*uid, position);
+    }
+    let common: Vec<(u32, usize)> = actual
+        .iter()
+        .filter_map(|uid| positions.get(uid).map(|position| (*uid, *position)))
+        .collect();
+
+    // tails[i] is the index in `common` of the smallest tail of an increasing run of length i + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; common.len()];
+    for (index, (_, position)) in common.iter().enumerate() {
+        let length = tails.partition_point(|tail| common[*tail].1 < *position);
+        previous[index] = length.checked_sub(1).map(|prev| tails[prev]);
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+    let mut in_order = vec![false; common.len()];
+    let mut next = tails.last().copied();
+    while let Some(index) = next {
+        in_order[index] = true;
+        next = previous[index];
+    }
+
+    common
+        .iter()
+        .zip(in_order)
+        .filter(|(_, in_order)| !in_order)
+        .map(|((uid, _), _)| *uid)
+        .collect()
+}
+
+/// Compare the economic quality of two block templates by their feerate diagrams.
+///
+/// Templates are lists of mempool uids; uids which are not in the mempool are ignored.
+pub fn compare_templates(
+    mempool: &Mempool,
+    fee_deltas: &FeeDeltas,
+    expected: &[u32],
+    actual: &[u32],
+) -> TemplateComparison {
+    let expected_diagram = template_diagram(mempool, fee_deltas, expected);
+    let actual_diagram = template_diagram(mempool, fee_deltas, actual);
+    let (expected_better, actual_better) = dominance(&expected_diagram, &actual_diagram);
+    let expected_end = expected_diagram.end();
+    let actual_end = actual_diagram.end();
+
+    TemplateComparison {
+        expected_fees: expected_end.fee,
+        actual_fees: actual_end.fee,
+        expected_vsize: expected_end.vsize,
+        actual_vsize: actual_end.vsize,
+        fee_difference: actual_end.fee - expected_end.fee,
+        fee_score: if expected_end.fee > 0.0 {
+            actual_end.fee / expected_end.fee
+        } else {
+            1.0
+        },
+        expected_better,
+        actual_better,
+        expected_diagram: expected_diagram.into_points(),
+        actual_diagram: actual_diagram.into_points(),
+    }
+}
+
+// linearize the template's transactions, as if it were the whole mempool
+fn template_diagram(
+    mempool: &Mempool,
+    fee_deltas: &FeeDeltas,
+    template: &[u32],
+) -> FeerateDiagram {
+    let (mut transactions, max_uid) = mempool.subset(template.iter().copied());
+    let result = gbt::gbt(
+        &mut transactions,
+        &GbtContext::new(fee_deltas),
+        max_uid,
+        u32::MAX,
+        1,
+        &GbtOptions {
+            diagram: Some(true),
+            ..GbtOptions::default()
+        },
+    );
+    FeerateDiagram::from_points(result.diagram.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_templates, diff_node_template, reordered};
+    use crate::{
+        getblocktemplate::{GetBlockTemplate, GetBlockTemplateTransaction},
+        mempool::Mempool,
+        thread_transaction::test_tx,
+        u32_hasher_types::u32hashmap_with_capacity,
+    };
+
+    #[test]
+    fn test_reordered() {
+        assert!(reordered(&[0, 1, 2, 3], &[0, 1, 2, 3]).is_empty());
+        assert_eq!(reordered(&[0, 1, 2, 3], &[3, 0, 1, 2]), vec![3]);
+        // either of a swapped pair could move, the later one stays in place
+        assert_eq!(reordered(&[0, 1, 2, 3], &[1, 0, 5, 3]), vec![1]);
+    }
+
+    #[test]
+    fn test_compare_templates() {
+        let mut mempool = Mempool::with_capacity(3);
+        for (uid, fee) in [(0, 1000.0), (1, 500.0), (2, 200.0)] {
+            mempool.insert(test_tx(uid, fee, 400, vec![]));
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+
+        // swapping tx 1 for the cheaper tx 2 is worse everywhere past tx 0
+        let dominated = compare_templates(&mempool, &no_deltas, &[0, 1], &[0, 2]);
+        assert_eq!(dominated.expected_fees, 1500.0);
+        assert_eq!(dominated.actual_fees, 1200.0);
+        assert_eq!(dominated.fee_difference, -300.0);
+        assert_eq!(dominated.fee_score, 0.8);
+        assert_eq!(dominated.expected_better, vec![vec![100.0, 200.0]]);
+        assert!(dominated.actual_better.is_empty());
+
+        let dominating = compare_templates(&mempool, &no_deltas, &[0, 2], &[0, 1]);
+        assert_eq!(dominating.fee_difference, 300.0);
+        assert!(dominating.expected_better.is_empty());
+        assert_eq!(dominating.actual_better, vec![vec![100.0, 200.0]]);
+
+        // templates are linearized, so the order they are given in does not matter
+        let equal = compare_templates(&mempool, &no_deltas, &[0, 1], &[1, 0]);
+        assert_eq!(equal.fee_difference, 0.0);
+        assert_eq!(equal.fee_score, 1.0);
+        assert!(equal.expected_better.is_empty());
+        assert!(equal.actual_better.is_empty());
+    }
+
+    #[test]
+    fn test_diff_node_template() {
+        let mut mempool = Mempool::with_capacity(3);
+        for uid in 0..3 {
+            mempool.insert(test_tx(uid, 1000.0, 400, vec![]));
+        }
+        let entry = |txid: String, fee: f64| GetBlockTemplateTransaction {
+            data: None,
+            txid: Some(txid),
+            hash: None,
+            depends: vec![],
+            fee,
+            sigops: 0,
+            weight: 400,
+        };
+        let template = GetBlockTemplate {
+            height: 840_000,
+            transactions: vec![
+                entry(format!("{:064x}", 1), 1000.0),
+                entry(format!("{:064x}", 0), 1000.0),
+                entry("ff".repeat(32), 5000.0),
+                // matching only the hash is not a match
+                GetBlockTemplateTransaction {
+                    txid: None,
+                    hash: Some(format!("{:064x}", 2)),
+                    ..entry(String::new(), 0.0)
+                },
+            ],
+            coinbase_value: 312_507_000.0,
+            min_time: 0,
+            size_limit: 4_000_000,
+            weight_limit: 4_000_000,
+            sigop_limit: 80_000,
+        };
+
+        let node_diff = diff_node_template(&mempool, &[0, 1, 2], &template);
+        assert_eq!(node_diff.diff.missing, vec![2]);
+        assert!(node_diff.diff.extra.is_empty());
+        assert_eq!(node_diff.diff.reordered, vec![1]);
+        assert_eq!(node_diff.unknown, vec!["ff".repeat(32), String::new()]);
+        assert_eq!(node_diff.diff.expected_fees, 3000.0);
+        assert_eq!(node_diff.diff.actual_fees, 7000.0);
+        assert_eq!(node_diff.fee_difference, 4000.0);
+    }
+}
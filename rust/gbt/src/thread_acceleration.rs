@@ -1,8 +1,22 @@
 use napi_derive::napi;
+use std::collections::HashMap;
 
-#[derive(Debug)]
+use crate::u32_hasher_types::{u32hashmap_with_capacity, U32HasherState};
+
+/// Fee deltas by uid, as set by accelerations or `prioritisetransaction`
+pub type FeeDeltas = HashMap<u32, f64, U32HasherState>;
+
+#[derive(Clone, Debug)]
 #[napi(object)]
 pub struct ThreadAcceleration {
     pub uid: u32,
     pub delta: f64, // fee delta
 }
+
+pub fn fee_deltas(accelerations: &[ThreadAcceleration]) -> FeeDeltas {
+    let mut deltas = u32hashmap_with_capacity(accelerations.len());
+    for acceleration in accelerations {
+        deltas.insert(acceleration.uid, acceleration.delta);
+    }
+    deltas
+}
@@ -0,0 +1,8 @@
+use napi_derive::napi;
+
+#[derive(Clone, Debug)]
+#[napi(object)]
+pub struct ThreadOutpoint {
+    pub txid: String,
+    pub vout: u32,
+}
@@ -1,6 +1,8 @@
 use napi_derive::napi;
 
-#[derive(Debug)]
+use crate::thread_outpoint::ThreadOutpoint;
+
+#[derive(Clone, Debug)]
 #[napi(object)]
 pub struct ThreadTransaction {
     pub uid: u32,
@@ -10,4 +12,51 @@ pub struct ThreadTransaction {
     pub sigops: u32,
     pub effective_fee_per_vsize: f64,
     pub inputs: Vec<u32>,
+    /// Needed to re-link mempool children when this transaction is re-added by a reorg
+    pub txid: Option<String>,
+    /// Every outpoint spent by this transaction, including confirmed ones
+    pub prevouts: Option<Vec<ThreadOutpoint>>,
+    /// When this transaction first entered the mempool, in unix seconds
+    pub first_seen: Option<f64>,
+    /// nLockTime, only if it is enforced (any input has a non-final sequence).
+    /// Kept apart from the raw `locktime` so final transactions are never held back.
+    pub enforced_locktime: Option<u32>,
+    /// The lowest block height which satisfies the BIP68 height-based sequence locks,
+    /// resolved from the confirmation heights of the inputs
+    pub relative_lock_height: Option<u32>,
+    /// The lowest parent median-time-past which satisfies the BIP68 time-based sequence locks,
+    /// resolved from the confirmation times of the inputs
+    pub relative_lock_time: Option<u32>,
+    /// Bitflags describing the transaction, like the backend's `TransactionFlags`
+    pub flags: Option<f64>,
+}
+
+impl ThreadTransaction {
+    /// sigop-adjusted vsize rounded up the the next integer
+    #[inline]
+    pub fn sigop_adjusted_vsize(&self) -> u32 {
+        ((self.weight + 3) / 4).max(self.sigops * 5)
+    }
+}
+
+/// A transaction paying `fee` over `weight` for tests, with a txid made from its uid
+/// and none of the other optional fields set. Override any fields with struct update syntax.
+#[cfg(test)]
+pub fn test_tx(uid: u32, fee: f64, weight: u32, inputs: Vec<u32>) -> ThreadTransaction {
+    ThreadTransaction {
+        uid,
+        order: uid,
+        fee,
+        weight,
+        sigops: 0,
+        effective_fee_per_vsize: fee / (f64::from(weight) / 4.0),
+        inputs,
+        txid: Some(format!("{uid:064x}")),
+        prevouts: None,
+        first_seen: None,
+        enforced_locktime: None,
+        relative_lock_height: None,
+        relative_lock_time: None,
+        flags: None,
+    }
 }
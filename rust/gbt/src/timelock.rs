@@ -0,0 +1,139 @@
+use crate::thread_transaction::ThreadTransaction;
+
+/// nLockTime values below this are block heights, and at or above it are unix timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Used to project the median-time-past of future blocks, in seconds
+const TARGET_BLOCK_SPACING: u32 = 600;
+
+/// The current best block, which projected blocks are built on top of.
+#[derive(Clone, Copy, Debug)]
+pub struct ChainTip {
+    pub height: u32,
+    pub median_time_past: u32,
+}
+
+impl ChainTip {
+    /// The index of the first projected block which could include this transaction
+    /// on its own timelocks, ignoring those of its ancestors.
+    pub fn min_block(self, tx: &ThreadTransaction) -> usize {
+        let mut min_block: u32 = 0;
+        if let Some(locktime) = tx.enforced_locktime {
+            // the locktime must be strictly below the block height or parent median-time-past
+            min_block = min_block.max(if locktime < LOCKTIME_THRESHOLD {
+                locktime.saturating_sub(self.height)
+            } else {
+                self.blocks_until_time(locktime.saturating_add(1))
+            });
+        }
+        if let Some(lock_height) = tx.relative_lock_height {
+            min_block = min_block.max(lock_height.saturating_sub(self.height + 1));
+        }
+        if let Some(lock_time) = tx.relative_lock_time {
+            min_block = min_block.max(self.blocks_until_time(lock_time));
+        }
+        min_block as usize
+    }
+
+    // The index of the first block whose parent median-time-past is at least `time`.
+    // Block 0 is built on the tip itself, and every later block is assumed to advance
+    // the median-time-past by the target block spacing.
+    const fn blocks_until_time(self, time: u32) -> u32 {
+        time.saturating_sub(self.median_time_past)
+            .div_ceil(TARGET_BLOCK_SPACING)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChainTip;
+    use crate::{
+        gbt::{gbt, GbtContext},
+        thread_transaction::{test_tx, ThreadTransaction},
+        u32_hasher_types::u32hashmap_with_capacity,
+        GbtOptions, UnmineableReason,
+    };
+
+    #[test]
+    fn test_min_block() {
+        let tip = ChainTip {
+            height: 800_000,
+            median_time_past: 1_700_000_000,
+        };
+        for (enforced_locktime, relative_lock_height, relative_lock_time, min_block) in [
+            (None, None, None, 0),
+            // the next block is at height 800_001
+            (Some(800_000), None, None, 0),
+            (Some(800_001), None, None, 1),
+            (None, Some(800_001), None, 0),
+            (None, Some(800_003), None, 2),
+            (Some(1_699_999_999), None, None, 0),
+            (Some(1_700_000_000), None, None, 1),
+            (None, None, Some(1_700_000_000), 0),
+            (None, None, Some(1_700_000_601), 2),
+            (Some(800_001), Some(800_005), None, 4),
+        ] {
+            let tx = ThreadTransaction {
+                enforced_locktime,
+                relative_lock_height,
+                relative_lock_time,
+                ..test_tx(0, 1000.0, 400, vec![])
+            };
+            assert_eq!(tip.min_block(&tx), min_block);
+        }
+    }
+
+    #[test]
+    fn test_deferred_blocks() {
+        let tip = ChainTip {
+            height: 800_000,
+            median_time_past: 1_700_000_000,
+        };
+        let mut mempool = u32hashmap_with_capacity(4);
+        for (uid, enforced_locktime, inputs) in [
+            (0, None, vec![]),
+            (1, Some(800_001), vec![]),
+            (2, None, vec![1]),
+            (3, Some(900_000), vec![]),
+        ] {
+            mempool.insert(
+                uid,
+                ThreadTransaction {
+                    enforced_locktime,
+                    ..test_tx(uid, 1000.0, 400, inputs)
+                },
+            );
+        }
+        let no_deltas = u32hashmap_with_capacity(0);
+        let context = GbtContext {
+            chain_tip: Some(tip),
+            ..GbtContext::new(&no_deltas)
+        };
+
+        let result = gbt(
+            &mut mempool,
+            &context,
+            3,
+            4_000_000,
+            8,
+            &GbtOptions::default(),
+        );
+        assert_eq!(result.blocks.len(), 2);
+        assert_eq!(result.blocks[0], vec![0]);
+        assert_eq!(result.blocks[1].len(), 2);
+        assert_eq!(result.overflow, vec![3]);
+        assert_eq!(result.unmineable[0].reason, UnmineableReason::Timelocked);
+
+        // a block with nothing valid yet stays empty
+        mempool.remove(&0);
+        let result = gbt(
+            &mut mempool,
+            &context,
+            3,
+            4_000_000,
+            8,
+            &GbtOptions::default(),
+        );
+        assert!(result.blocks[0].is_empty());
+        assert_eq!(result.blocks[1].len(), 2);
+    }
+}